@@ -0,0 +1,60 @@
+// fracgen
+// Thread scaling benchmark
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Renders the same frame with 1, 2, 4, ... threads up to the core count and
+// prints the time and speedup over one thread for each.
+// Run with `cargo bench --bench threads`
+
+use std::time::Instant;
+
+use fracgen::{Args, Functs, Renderer};
+
+fn render(args: &Args, threads: usize) -> f64 {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let mut rend = Renderer::new(args.clone(), Functs::from_args(args));
+    let now = Instant::now();
+    pool.install(|| rend.render_samples(args.samples, false));
+    now.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let args = Args {
+        width: 512,
+        height: 512,
+        samples: 4,
+        limit: 2048.0,
+        seed: Some(1),
+        ..Args::new()
+    };
+    let cores = num_cpus::get();
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|n| *n < cores)
+        .collect();
+    counts.push(cores);
+    // warm up caches and the thread pool machinery
+    render(&args, cores);
+    let base = render(&args, 1);
+    println!("threads  seconds  speedup");
+    println!("{:7}  {:7.3}  {:7.2}", 1, base, 1.0);
+    for threads in counts.into_iter().skip(1) {
+        let t = render(&args, threads);
+        println!("{:7}  {:7.3}  {:7.2}", threads, t, base / t);
+    }
+}
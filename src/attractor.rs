@@ -0,0 +1,258 @@
+// fracgen
+// Strange attractors
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// 2D maps iterated from random starting points, every point after the
+// warmup counts a hit for its pixel like in the Buddhabrot. The histogram
+// keeps how often each pixel was hit and the sum of a coloring value over the
+// hits, the speed the orbit moved at or how far into its run it was.
+// Brightness comes from the hit count through `--tone`, color from the
+// average value through the `--color-mode` palette.
+
+use std::{fmt, io, str::FromStr, time::SystemTime};
+
+use num::complex::Complex;
+use rand::Rng;
+
+use crate::{
+    density::{white_points, Tone},
+    iterdata::Sample,
+    renderer::Renderer,
+};
+
+type Cf64 = Complex<f64>;
+
+/// Steps each orbit takes before it's plotted, to land on the attractor
+const WARMUP: usize = 64;
+/// Points plotted per orbit before starting over somewhere else, so one
+/// orbit that escapes or falls into a cycle doesn't waste a whole job
+const RUN: usize = 1 << 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attractor {
+    Clifford,
+    DeJong,
+    Henon,
+    Ikeda,
+}
+
+impl FromStr for Attractor {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "clifford" => Ok(Attractor::Clifford),
+            "dejong" => Ok(Attractor::DeJong),
+            "henon" => Ok(Attractor::Henon),
+            "ikeda" => Ok(Attractor::Ikeda),
+            _ => Err(format!(
+                "unknown attractor {}, expected clifford, dejong, henon or ikeda",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Attractor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Attractor::Clifford => "clifford",
+            Attractor::DeJong => "dejong",
+            Attractor::Henon => "henon",
+            Attractor::Ikeda => "ikeda",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Attractor {
+    /// Parameters used when `--params` isn't given, known to be chaotic
+    pub fn default_params(&self) -> Vec<f64> {
+        match self {
+            Attractor::Clifford => vec![-1.4, 1.6, 1.0, 0.7],
+            Attractor::DeJong => vec![1.4, -2.3, 2.4, -2.1],
+            Attractor::Henon => vec![1.4, 0.3],
+            Attractor::Ikeda => vec![0.9],
+        }
+    }
+
+    /// One step of the map from `p` with parameters `k`
+    pub fn step(&self, p: Cf64, k: &[f64]) -> Cf64 {
+        let (x, y) = (p.re, p.im);
+        match self {
+            Attractor::Clifford => Cf64::new(
+                (k[0] * y).sin() + k[2] * (k[0] * x).cos(),
+                (k[1] * x).sin() + k[3] * (k[1] * y).cos(),
+            ),
+            Attractor::DeJong => Cf64::new(
+                (k[0] * y).sin() - (k[1] * x).cos(),
+                (k[2] * x).sin() - (k[3] * y).cos(),
+            ),
+            Attractor::Henon => Cf64::new(1.0 - k[0] * x * x + y, k[1] * x),
+            Attractor::Ikeda => {
+                let t = 0.4 - 6.0 / (1.0 + x * x + y * y);
+                let (s, c) = t.sin_cos();
+                Cf64::new(1.0 + k[0] * (x * c - y * s), k[0] * (x * s + y * c))
+            }
+        }
+    }
+}
+
+impl Renderer {
+    /// Renders `--samples` points per pixel of the `--attractor` into `image`
+    pub fn render_attractor(&mut self, progress: bool) -> io::Result<()> {
+        let attractor = match self.args.attractor {
+            Some(attractor) => attractor,
+            None => return Ok(()),
+        };
+        let params = match self.args.params.len() {
+            0 => attractor.default_params(),
+            _ => self.args.params.clone(),
+        };
+        if params.len() != attractor.default_params().len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the {} attractor takes {} parameters",
+                    attractor,
+                    attractor.default_params().len()
+                ),
+            ));
+        }
+        let now = SystemTime::now();
+        // hits and the sum of the coloring value per pixel
+        let hist = self.histogram(2, progress, |rng, n, hist| {
+            let mut done = 0;
+            while done < n {
+                let mut p = Cf64::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1));
+                for _ in 0..WARMUP {
+                    p = attractor.step(p, &params);
+                }
+                let run = RUN.min(n - done);
+                for age in 0..run {
+                    let next = attractor.step(p, &params);
+                    if !next.is_finite() {
+                        break;
+                    }
+                    if let Some(i) = self.density_pixel(next) {
+                        hist[i * 2] += 1.0;
+                        hist[i * 2 + 1] += match self.args.age {
+                            true => age as f64 / RUN as f64,
+                            false => (next - p).norm(),
+                        };
+                    }
+                    p = next;
+                }
+                done += run;
+            }
+        });
+        let white = white_points(&hist, 2)[0];
+        let tone = self.args.tone.unwrap_or(Tone::Log);
+        let average = |i: usize| hist[i * 2 + 1] / hist[i * 2].max(1.0);
+        // velocities are scaled so the fastest pixel gets the palette's end
+        let top = (0..hist.len() / 2)
+            .map(average)
+            .fold(0.0f64, f64::max)
+            .max(f64::MIN_POSITIVE);
+        let colors: Vec<_> = (0..hist.len() / 2)
+            .map(|i| {
+                let t = match self.args.age {
+                    true => average(i),
+                    false => average(i) / top,
+                };
+                let e = Sample {
+                    i: t * self.args.limit,
+                    s: t * self.args.limit,
+                    z: Cf64::new(1.0, 0.0),
+                    der: Cf64::new(1.0, 0.0),
+                    period: 0,
+                };
+                // squared like accumulated samples, `process_image` undoes it
+                let color = self.palette(&e);
+                let mut color = color * color;
+                let brightness = tone.map(hist[i * 2], white);
+                for ch in color.ch[..3].iter_mut() {
+                    *ch *= brightness * brightness;
+                }
+                color
+            })
+            .collect();
+        for (raw, color) in self.raw.iter_mut().flatten().zip(colors) {
+            *raw = color;
+        }
+        for row in self.weights.iter_mut() {
+            row.fill(1.0);
+        }
+        self.rendered_samples = 1;
+        self.process_image();
+        if progress {
+            println!("{:4.4}", now.elapsed().unwrap().as_secs_f32());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functs};
+
+    const ATTRACTORS: [Attractor; 4] = [
+        Attractor::Clifford,
+        Attractor::DeJong,
+        Attractor::Henon,
+        Attractor::Ikeda,
+    ];
+
+    #[test]
+    fn names_round_trip() {
+        for attractor in ATTRACTORS {
+            assert_eq!(attractor.to_string().parse::<Attractor>(), Ok(attractor));
+        }
+    }
+
+    #[test]
+    fn henon_takes_its_textbook_step() {
+        let p = Attractor::Henon.step(Cf64::new(0.5, 0.2), &[1.4, 0.3]);
+        assert!((p - Cf64::new(1.0 - 1.4 * 0.25 + 0.2, 0.15)).norm() < 1e-15);
+    }
+
+    #[test]
+    fn default_orbits_stay_bounded() {
+        for attractor in ATTRACTORS {
+            let params = attractor.default_params();
+            let mut p = Cf64::new(0.05, 0.05);
+            for _ in 0..10000 {
+                p = attractor.step(p, &params);
+            }
+            assert!(p.norm() < 10.0, "{} {}", attractor, p);
+        }
+    }
+
+    #[test]
+    fn renders_light_the_attractor_and_check_params() {
+        let mut args = Args::new();
+        (args.width, args.height, args.samples) = (48, 48, 4);
+        (args.attractor, args.seed) = (Some(Attractor::Clifford), Some(1));
+        args.zoom = 0.45;
+        args.origin = Cf64::new(0.0, 0.0);
+        let mut rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        rend.render_attractor(false).unwrap();
+        assert!(rend.image.pixels().any(|p| p.0[..3] != [0, 0, 0]));
+        args.params = vec![1.0];
+        let mut rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        assert!(rend.render_attractor(false).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+// fracgen
+// Render checkpoints and accumulators
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    color::{Color, ColorType},
+    iterdata::{read_f64, read_u32, read_u64},
+    renderer::{Args, Renderer},
+    sampler,
+};
+
+const MAGIC: &[u8; 4] = b"FGCK";
+const VERSION: u32 = 7;
+
+/// Which of the optional buffers follow the header, see `Renderer::allocate`
+const SECOND_MOMENT: u8 = 1;
+const WEIGHTS: u8 = 2;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Hash of the scene `args` renders, without the settings that only decide
+/// how many samples are taken or where the image goes. Accumulators with
+/// different hashes hold different pictures
+fn scene_hash(args: &Args) -> u64 {
+    let defaults = Args::new();
+    let view = Args {
+        name: defaults.name,
+        samples: defaults.samples,
+        noise: defaults.noise,
+        max_samples: defaults.max_samples,
+        adaptive_step: defaults.adaptive_step,
+        send_iters: defaults.send_iters,
+        seed: None,
+        ..args.clone()
+    };
+    view.to_scene()
+        .bytes()
+        .fold(0, |h, b| sampler::hash(h ^ b as u64))
+}
+
+/// What a checkpoint says about itself before its buffers
+struct Header {
+    seeds: Vec<u64>,
+    rendered_samples: usize,
+    pass: usize,
+    done: usize,
+}
+
+impl Renderer {
+    /// Writes the accumulator and how far the current pass of `pass` samples
+    /// got (`done` pixels in render order), `pass == 0` means between passes.
+    /// The file is written next to `path` first so a crash can't corrupt it
+    pub fn save_checkpoint(&self, path: &str, pass: usize, done: usize) -> io::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            w.write_all(MAGIC)?;
+            for v in [
+                VERSION,
+                self.width as u32,
+                self.height as u32,
+                self.offset.0 as u32,
+                self.offset.1 as u32,
+            ] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&scene_hash(&self.args).to_le_bytes())?;
+            w.write_all(&(self.seeds.len() as u32).to_le_bytes())?;
+            for seed in self.seeds.iter() {
+                w.write_all(&seed.to_le_bytes())?;
+            }
+            for v in [self.rendered_samples, pass, done] {
+                w.write_all(&(v as u64).to_le_bytes())?;
+            }
+            w.write_all(&[self.buffers()])?;
+            for buf in [&self.raw, &self.raw_sq] {
+                for row in buf.iter() {
+                    for e in row.iter() {
+                        for v in e.ch {
+                            w.write_all(&v.to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+            for row in self.extra_samples.iter() {
+                for e in row.iter() {
+                    w.write_all(&(*e as u64).to_le_bytes())?;
+                }
+            }
+            for row in self.weights.iter() {
+                for e in row.iter() {
+                    w.write_all(&e.to_le_bytes())?;
+                }
+            }
+            w.flush()?;
+        }
+        fs::rename(tmp, path)
+    }
+
+    /// Restores the accumulator from `path`, returning the interrupted pass's
+    /// sample count and how many pixels of it were done, for `resume_samples`.
+    /// The checkpoint has to be of the same scene and have samples of `--seed`
+    /// (none for unseeded renders), so the new samples carry on its sequence
+    pub fn load_checkpoint(&mut self, path: &str) -> io::Result<(usize, usize)> {
+        let mut r = BufReader::new(File::open(path)?);
+        let header = self.read_header(&mut r)?;
+        let seeded = match self.args.seed {
+            Some(seed) => header.seeds.contains(&seed),
+            None => header.seeds.is_empty(),
+        };
+        if !seeded {
+            return Err(invalid("checkpoint was rendered with a different --seed"));
+        }
+        for buf in [&mut self.raw, &mut self.raw_sq] {
+            for row in buf.iter_mut() {
+                for e in row.iter_mut() {
+                    *e = read_color(&mut r)?;
+                }
+            }
+        }
+        for row in self.extra_samples.iter_mut() {
+            for e in row.iter_mut() {
+                *e = read_u64(&mut r)? as usize;
+            }
+        }
+        for row in self.weights.iter_mut() {
+            for e in row.iter_mut() {
+                *e = read_f64(&mut r)?;
+            }
+        }
+        self.rendered_samples = header.rendered_samples;
+        self.seeds = header.seeds;
+        Ok((header.pass, header.done))
+    }
+
+    /// Adds the accumulator in `path` to this one, so renders of the same frame
+    /// with different seeds (or on different machines) add up to more samples.
+    /// Both have to be of the same scene, and a seed already in this one would
+    /// only add the same samples again
+    pub fn merge_accum(&mut self, path: &str) -> io::Result<()> {
+        let mut r = BufReader::new(File::open(path)?);
+        let header = self.read_header(&mut r)?;
+        if header.pass > 0 {
+            return Err(invalid(
+                "accumulator has an unfinished pass, resume it first",
+            ));
+        }
+        if let Some(seed) = header.seeds.iter().find(|s| self.seeds.contains(s)) {
+            return Err(invalid(&format!(
+                "both accumulators have samples of seed {}, render the parts with different seeds",
+                seed
+            )));
+        }
+        for buf in [&mut self.raw, &mut self.raw_sq] {
+            for row in buf.iter_mut() {
+                for e in row.iter_mut() {
+                    *e = *e + read_color(&mut r)?;
+                }
+            }
+        }
+        for row in self.extra_samples.iter_mut() {
+            for e in row.iter_mut() {
+                *e += read_u64(&mut r)? as usize;
+            }
+        }
+        for row in self.weights.iter_mut() {
+            for e in row.iter_mut() {
+                *e += read_f64(&mut r)?;
+            }
+        }
+        self.rendered_samples += header.rendered_samples;
+        self.seeds.extend(header.seeds);
+        Ok(())
+    }
+
+    /// Records that the accumulator now has samples of `--seed`
+    pub(crate) fn note_seed(&mut self) {
+        if let Some(seed) = self.args.seed {
+            if !self.seeds.contains(&seed) {
+                self.seeds.push(seed);
+            }
+        }
+    }
+
+    fn read_header(&self, r: &mut impl Read) -> io::Result<Header> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a fracgen checkpoint"));
+        }
+        if read_u32(r)? != VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let (w, h) = (read_u32(r)? as i32, read_u32(r)? as i32);
+        let offset = (read_u32(r)? as i32, read_u32(r)? as i32);
+        if (w, h, offset) != (self.width, self.height, self.offset) {
+            return Err(invalid("checkpoint was made for a different frame"));
+        }
+        let scene = read_u64(r)?;
+        if scene != scene_hash(&self.args) {
+            return Err(invalid(
+                "checkpoint was made for a different scene (view, formula, colors or pattern)",
+            ));
+        }
+        let seeds = (0..read_u32(r)?)
+            .map(|_| read_u64(r))
+            .collect::<io::Result<Vec<u64>>>()?;
+        let header = Header {
+            seeds,
+            rendered_samples: read_u64(r)? as usize,
+            pass: read_u64(r)? as usize,
+            done: read_u64(r)? as usize,
+        };
+        let mut buffers = [0u8];
+        r.read_exact(&mut buffers)?;
+        if buffers[0] != self.buffers() {
+            return Err(invalid(
+                "checkpoint was saved with a different --noise setting",
+            ));
+        }
+        Ok(header)
+    }
+
+    fn buffers(&self) -> u8 {
+        let mut flags = 0;
+        if !self.raw_sq.is_empty() {
+            flags |= SECOND_MOMENT;
+        }
+        if !self.weights.is_empty() {
+            flags |= WEIGHTS;
+        }
+        flags
+    }
+}
+
+fn read_color(r: &mut impl Read) -> io::Result<Color> {
+    let mut ch = [0f64; 4];
+    for v in ch.iter_mut() {
+        *v = read_f64(r)?;
+    }
+    Ok(Color::new(ch, ColorType::SRgba))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Functs;
+
+    fn args() -> Args {
+        let mut args = Args::new();
+        (args.width, args.height, args.samples, args.limit) = (40, 36, 2, 128.0);
+        args.seed = Some(11);
+        args
+    }
+
+    /// Keeps every optional buffer of the accumulator
+    fn adaptive() -> Args {
+        Args {
+            noise: 0.05,
+            ..args()
+        }
+    }
+
+    fn renderer(args: &Args) -> Renderer {
+        Renderer::new(args.clone(), Functs::from_args(args))
+    }
+
+    fn temp(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("fracgen-{}-{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn resumed_render_is_bit_identical() {
+        let args = adaptive();
+        let mut whole = renderer(&args);
+        whole.render_samples(2, false);
+        whole.render_samples(2, false);
+
+        // the second pass was stopped a third of the way through: pixels
+        // before `done` in render order have it, the rest don't yet
+        let done = (args.width * args.height / 3) as usize;
+        let mut stopped = renderer(&args);
+        stopped.render_samples(2, false);
+        for i in 0..done {
+            let (x, y) = stopped.local(i as i32);
+            stopped.raw[y][x] = whole.raw[y][x];
+            stopped.raw_sq[y][x] = whole.raw_sq[y][x];
+            stopped.weights[y][x] = whole.weights[y][x];
+        }
+        let path = temp("resume");
+        stopped.save_checkpoint(&path, 2, done).unwrap();
+
+        let mut resumed = renderer(&args);
+        let (pass, start) = resumed.load_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((pass, start), (2, done));
+        resumed.resume_samples(pass, start, false);
+        assert_eq!(resumed.rendered_samples, whole.rendered_samples);
+        let bits = |r: &Renderer| -> Vec<u64> {
+            r.raw
+                .iter()
+                .chain(r.raw_sq.iter())
+                .flatten()
+                .flat_map(|c| c.ch.map(f64::to_bits))
+                .collect()
+        };
+        assert_eq!(bits(&resumed), bits(&whole));
+    }
+
+    #[test]
+    fn merges_need_the_same_scene_and_other_seeds() {
+        let args = args();
+        let paths = [temp("merge-a"), temp("merge-b"), temp("merge-c")];
+        for (path, seed, zoom) in [
+            (&paths[0], 1, 0.7),
+            (&paths[1], 2, 0.7),
+            (&paths[2], 3, 2.0),
+        ] {
+            let mut rend = renderer(&Args {
+                seed: Some(seed),
+                zoom,
+                ..args.clone()
+            });
+            rend.render_samples(1, false);
+            rend.save_checkpoint(path, 0, 0).unwrap();
+        }
+        let mut merged = renderer(&args);
+        merged.merge_accum(&paths[0]).unwrap();
+        assert!(merged.merge_accum(&paths[0]).is_err());
+        merged.merge_accum(&paths[1]).unwrap();
+        assert!(merged.merge_accum(&paths[2]).is_err());
+        assert_eq!(
+            (merged.rendered_samples, &merged.seeds[..]),
+            (2, &[1, 2][..])
+        );
+        for path in paths.iter() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn other_scenes_and_seeds_are_refused() {
+        let args = args();
+        let mut rend = renderer(&args);
+        rend.render_samples(1, false);
+        let path = temp("refuse");
+        rend.save_checkpoint(&path, 0, 0).unwrap();
+        let zoomed = Args {
+            zoom: 2.0,
+            ..args.clone()
+        };
+        assert!(renderer(&zoomed).load_checkpoint(&path).is_err());
+        let reseeded = Args {
+            seed: Some(12),
+            ..args.clone()
+        };
+        assert!(renderer(&reseeded).load_checkpoint(&path).is_err());
+        assert!(renderer(&adaptive()).load_checkpoint(&path).is_err());
+        // only how many samples to take changed
+        let more = Args {
+            samples: 8,
+            ..args.clone()
+        };
+        assert!(renderer(&more).load_checkpoint(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+// fracgen
+// Orbit density rendering
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The Buddhabrot: instead of coloring each pixel by its own orbit, random
+// points `c` are iterated and every point of their orbit that lands in the
+// view counts a hit for its pixel. Only orbits that escape within the limit
+// are counted, or only the ones that don't for the anti-Buddhabrot. With
+// `--nebula` each color channel has its own limit.
+//
+// Deep in a zoom almost no orbit passes through the view, so `--metropolis`
+// walks a Metropolis-Hastings chain over `c` that favours orbits with many
+// hits instead. Each orbit is then counted with weight 1 / hits, which undoes
+// the bias of the chain, so both ways converge to the same image.
+
+use std::{fmt, io, str::FromStr, sync::atomic::Ordering, time::SystemTime};
+
+use image::Rgba;
+use num::complex::Complex;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{progress::with_progress, renderer::Renderer, sampler};
+
+type Cf64 = Complex<f64>;
+
+/// Orbits are sampled from this square, every escaping `c` of the built-in
+/// formulas lies in it
+const DOMAIN: f64 = 2.0;
+/// Samples one job takes, each job has its own random source
+const JOB: usize = 1 << 14;
+/// How often the chain jumps to a fresh random `c`, keeps it from getting
+/// stuck around one bright orbit
+const LARGE_STEP: f64 = 0.1;
+/// Random points tried to find the chain's first orbit that hits the view
+const SEED_TRIES: usize = 100_000;
+/// Fraction of lit pixels allowed to be brighter than white, so a few
+/// pixels with huge counts don't leave the rest of the image dark
+const CLIP: f64 = 0.001;
+
+/// Curve from hit counts (scaled to [0, 1]) to brightness
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tone {
+    Linear,
+    Sqrt,
+    Log,
+}
+
+impl FromStr for Tone {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "linear" => Ok(Tone::Linear),
+            "sqrt" => Ok(Tone::Sqrt),
+            "log" => Ok(Tone::Log),
+            _ => Err(format!(
+                "unknown tone curve {}, expected linear, sqrt or log",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Tone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Tone::Linear => "linear",
+            Tone::Sqrt => "sqrt",
+            Tone::Log => "log",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Tone {
+    /// Brightness in [0, 1] of a count of `v` when `white` is full brightness
+    pub fn map(&self, v: f64, white: f64) -> f64 {
+        if white <= 0.0 {
+            return 0.0;
+        }
+        let t = match self {
+            Tone::Linear => v / white,
+            Tone::Sqrt => (v / white).sqrt(),
+            Tone::Log => (1.0 + v).ln() / (1.0 + white).ln(),
+        };
+        t.clamp(0.0, 1.0)
+    }
+}
+
+/// The count every channel's white point is set to: all but `CLIP` of the
+/// lit pixels are at or below it. `hist` interleaves `channels` channels
+pub fn white_points(hist: &[f64], channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|k| {
+            let mut lit: Vec<f64> = hist
+                .iter()
+                .skip(k)
+                .step_by(channels)
+                .copied()
+                .filter(|v| *v > 0.0)
+                .collect();
+            if lit.is_empty() {
+                return 0.0;
+            }
+            let n = ((lit.len() - 1) as f64 * (1.0 - CLIP)) as usize;
+            *lit.select_nth_unstable_by(n, f64::total_cmp).1
+        })
+        .collect()
+}
+
+impl Renderer {
+    /// The iteration limit of each histogram channel, one for plain
+    /// Buddhabrots and three (red, green, blue) with `--nebula`
+    fn channel_limits(&self) -> Vec<f64> {
+        match self.args.nebula.len() {
+            0 => vec![self.args.limit],
+            _ => self.args.nebula.clone(),
+        }
+    }
+
+    /// Histogram index of the pixel `z` lands in, if it's in the view
+    pub(crate) fn density_pixel(&self, z: Cf64) -> Option<usize> {
+        let (w, h) = (self.args.width as f64, self.args.height as f64);
+        // `normalize_coords` backwards, pixel centers are at whole numbers
+        let d = z - self.args.origin;
+        let x = ((d.re * self.args.zoom + 1.0) * 0.5 * w + 0.5).floor();
+        let y = ((d.im * self.args.zoom * (w / h) + 1.0) * 0.5 * h + 0.5).floor();
+        if x >= 0.0 && y >= 0.0 && x < w && y < h {
+            Some(y as usize * self.args.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Iterates `c` and returns the histogram cells its orbit adds to, one
+    /// entry per hit. `orbit` is scratch space
+    fn orbit_hits(&self, c: Cf64, limits: &[f64], orbit: &mut Vec<Cf64>) -> Vec<usize> {
+        let max = limits.iter().fold(0.0f64, |a, b| a.max(*b)) as usize;
+        if !self.args.anti && (self.functs.interior_test)(c).is_some() {
+            return Vec::new();
+        }
+        let param = self.functs.param;
+        let k = self.functs.added(&self.args, c);
+        let bail = self.functs.bail;
+        let mut state = self
+            .functs
+            .start(self.args.z_init, c, k, self.args.is_julia);
+        orbit.clear();
+        let mut escaped = false;
+        while orbit.len() < max {
+            self.functs.formula(state.n).step(&mut state, k, param);
+            // NaN counts as escaped too
+            if state.z.norm_sqr() < bail {
+                orbit.push(state.z);
+            } else {
+                escaped = true;
+                break;
+            }
+        }
+        let mut hits = Vec::new();
+        for (k, limit) in limits.iter().enumerate() {
+            // escaped within this channel's limit
+            let counted = escaped && (orbit.len() as f64) < *limit;
+            if counted == self.args.anti {
+                continue;
+            }
+            let steps = orbit.len().min(*limit as usize);
+            hits.extend(
+                orbit[..steps]
+                    .iter()
+                    .filter_map(|z| self.density_pixel(*z))
+                    .map(|p| p * limits.len() + k),
+            );
+        }
+        hits
+    }
+
+    fn random_c(rng: &mut dyn RngCore) -> Cf64 {
+        Cf64::new(
+            rng.gen_range(-DOMAIN..DOMAIN),
+            rng.gen_range(-DOMAIN..DOMAIN),
+        )
+    }
+
+    /// `n` uniformly random orbits into `hist`
+    fn uniform_job(&self, rng: &mut dyn RngCore, n: usize, limits: &[f64], hist: &mut [f64]) {
+        let mut orbit = Vec::new();
+        for _ in 0..n {
+            for p in self.orbit_hits(Renderer::random_c(rng), limits, &mut orbit) {
+                hist[p] += 1.0;
+            }
+        }
+    }
+
+    /// `n` steps of a Metropolis-Hastings chain into `hist`
+    fn metropolis_job(&self, rng: &mut dyn RngCore, n: usize, limits: &[f64], hist: &mut [f64]) {
+        let mut orbit = Vec::new();
+        let mut c = Cf64::new(0.0, 0.0);
+        let mut hits = Vec::new();
+        for _ in 0..SEED_TRIES {
+            c = Renderer::random_c(rng);
+            hits = self.orbit_hits(c, limits, &mut orbit);
+            if !hits.is_empty() {
+                break;
+            }
+        }
+        if hits.is_empty() {
+            return;
+        }
+        // small steps are relative to the view, from a tenth of it down
+        let span = 1.0 / self.args.zoom;
+        for _ in 0..n {
+            let next = if rng.gen::<f64>() < LARGE_STEP {
+                Renderer::random_c(rng)
+            } else {
+                let r = span * 0.1 * (-4.0 * rng.gen::<f64>()).exp();
+                c + Cf64::from_polar(r, rng.gen_range(0.0..std::f64::consts::TAU))
+            };
+            if next.re.abs() < DOMAIN && next.im.abs() < DOMAIN {
+                let next_hits = self.orbit_hits(next, limits, &mut orbit);
+                // both steps are symmetric, so only the hit counts decide
+                if !next_hits.is_empty()
+                    && rng.gen::<f64>() * (hits.len() as f64) < next_hits.len() as f64
+                {
+                    c = next;
+                    hits = next_hits;
+                }
+            }
+            let weight = 1.0 / hits.len() as f64;
+            for p in hits.iter() {
+                hist[*p] += weight;
+            }
+        }
+    }
+
+    /// Runs `--samples` per pixel worth of `job`s in parallel and sums the
+    /// histograms (`channels` per pixel) they fill. A job gets its random
+    /// source, how many samples to take and a histogram of its thread
+    pub(crate) fn histogram(
+        &self,
+        channels: usize,
+        progress: bool,
+        job: impl Fn(&mut dyn RngCore, usize, &mut [f64]) + Sync,
+    ) -> Vec<f64> {
+        let len = self.args.width as usize * self.args.height as usize * channels;
+        let total = self.args.width as usize * self.args.height as usize * self.args.samples;
+        let jobs = total.div_ceil(JOB);
+        with_progress(total, 0, progress, |counter| {
+            (0..jobs)
+                .into_par_iter()
+                .fold(
+                    || vec![0.0; len],
+                    |mut hist, index| {
+                        let n = JOB.min(total - index * JOB);
+                        let mut rng: Box<dyn RngCore> = match self.args.seed {
+                            Some(seed) => Box::new(StdRng::seed_from_u64(sampler::hash(
+                                seed ^ sampler::hash(index as u64),
+                            ))),
+                            None => Box::new(rand::thread_rng()),
+                        };
+                        job(&mut *rng, n, &mut hist);
+                        counter.fetch_add(n, Ordering::Relaxed);
+                        hist
+                    },
+                )
+                .reduce(
+                    || vec![0.0; len],
+                    |mut a, b| {
+                        a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a += b);
+                        a
+                    },
+                )
+        })
+    }
+
+    /// Renders the orbit density of `--samples` orbits per pixel into `image`
+    pub fn render_density(&mut self, progress: bool) -> io::Result<()> {
+        if !matches!(self.args.nebula.len(), 0 | 3) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "--nebula takes three limits, for red, green and blue",
+            ));
+        }
+        let now = SystemTime::now();
+        let limits = self.channel_limits();
+        let channels = limits.len();
+        let hist = self.histogram(channels, progress, |rng, n, hist| {
+            if self.args.metropolis {
+                self.metropolis_job(rng, n, &limits, hist);
+            } else {
+                self.uniform_job(rng, n, &limits, hist);
+            }
+        });
+        let white = white_points(&hist, channels);
+        let tone = self.args.tone.unwrap_or(Tone::Linear);
+        for (i, p) in self.image.pixels_mut().enumerate() {
+            let v: Vec<u8> = (0..channels)
+                .map(|k| {
+                    let t = tone.map(hist[i * channels + k], white[k]);
+                    (t * 255.0).round() as u8
+                })
+                .collect();
+            *p = match channels {
+                1 => Rgba([v[0], v[0], v[0], 255]),
+                _ => Rgba([v[0], v[1], v[2], 255]),
+            };
+        }
+        if progress {
+            println!("{:4.4}", now.elapsed().unwrap().as_secs_f32());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functs};
+
+    #[test]
+    fn tones_map_black_to_white() {
+        for tone in [Tone::Linear, Tone::Sqrt, Tone::Log] {
+            assert_eq!(tone.to_string().parse::<Tone>(), Ok(tone));
+            assert_eq!(tone.map(0.0, 50.0), 0.0);
+            assert_eq!(tone.map(50.0, 50.0), 1.0);
+            assert_eq!(tone.map(500.0, 50.0), 1.0);
+            assert!(tone.map(10.0, 50.0) < tone.map(20.0, 50.0));
+            assert_eq!(tone.map(5.0, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn white_points_are_per_channel() {
+        // channel 0 counts 1..=2000, channel 1 is dark
+        let hist: Vec<f64> = (1..=2000).flat_map(|v| [v as f64, 0.0]).collect();
+        let white = white_points(&hist, 2);
+        assert_eq!(white, vec![1998.0, 0.0]);
+    }
+
+    /// The normalized histogram of a small seeded Buddhabrot
+    fn histogram(metropolis: bool) -> (Renderer, Vec<f64>) {
+        let args = Args {
+            width: 48,
+            height: 36,
+            samples: 40,
+            limit: 64.0,
+            zoom: 0.4,
+            seed: Some(5),
+            buddha: true,
+            metropolis,
+            ..Args::new()
+        };
+        let rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        let limits = rend.channel_limits();
+        let hist = rend.histogram(1, false, |rng, n, hist| match metropolis {
+            true => rend.metropolis_job(rng, n, &limits, hist),
+            false => rend.uniform_job(rng, n, &limits, hist),
+        });
+        let total: f64 = hist.iter().sum();
+        assert!(total > 0.0);
+        (rend, hist.iter().map(|v| v / total).collect())
+    }
+
+    #[test]
+    fn orbits_stay_in_the_escape_region() {
+        let (rend, hist) = histogram(false);
+        let lit = hist.iter().filter(|v| **v > 0.0).count();
+        assert!(lit > hist.len() / 2, "{} of {}", lit, hist.len());
+        // an orbit that leaves |z| < 2 escapes within a few steps, so pixels
+        // inside that circle are hit far more often than the ones past it
+        let d = rend.pixel_delta();
+        let near: Vec<bool> = (0..hist.len() as i32)
+            .map(|i| {
+                rend.point(i % rend.width, i / rend.width, d, (0.5, 0.5))
+                    .norm()
+                    < 2.0
+            })
+            .collect();
+        let area = near.iter().filter(|n| **n).count() as f64 / hist.len() as f64;
+        let inside: f64 = hist
+            .iter()
+            .zip(near.iter())
+            .filter(|(_, n)| **n)
+            .map(|(v, _)| v)
+            .sum();
+        let ratio = (inside / area) / ((1.0 - inside) / (1.0 - area));
+        assert!(ratio > 2.0, "{} {}", inside, area);
+    }
+
+    #[test]
+    fn metropolis_agrees_with_uniform_sampling() {
+        let (rend, uniform) = histogram(false);
+        let (_, chain) = histogram(true);
+        // compared in 6x6 blocks, single pixels are too noisy
+        let block = |hist: &[f64]| {
+            let mut blocks = vec![0.0; 8 * 6];
+            for (i, v) in hist.iter().enumerate() {
+                let (x, y) = (i as i32 % rend.width, i as i32 / rend.width);
+                blocks[(y / 6 * 8 + x / 6) as usize] += v;
+            }
+            blocks
+        };
+        let diff: f64 = block(&uniform)
+            .iter()
+            .zip(block(&chain).iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        assert!(diff < 0.3, "{}", diff);
+    }
+}
@@ -0,0 +1,382 @@
+// fracgen
+// Distributed rendering
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Wire format, everything little endian:
+//   coordinator -> worker: scene (u32 length + text), then per job
+//     TILE x y w h (u32 each) or DONE
+//   worker -> coordinator: per tile
+//     ACCUM rendered_samples (u64) + w*h colors (4 f64, row major)
+//       + w*h filter weights (f64, row major) + count (u32, w*h with
+//       `--noise`, otherwise 0) extra adaptive samples per pixel (u32,
+//       row major) or
+//     ITERS samples (u32) + w*h*samples `Sample`s in render order or, when
+//     `--filter` spreads samples over their neighbours,
+//     SPLATS count (u32) + count * (x, y (f64 frame pixels) + `Sample`) for
+//       the samples of the tile's pixels, wherever they land
+//
+// A worker that doesn't answer within `--worker-timeout` seconds is dropped
+// and its tile handed out again.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    color::{Color, ColorType},
+    iterdata::{read_f64, read_u32, read_u64, IterData, Sample},
+    renderer::{block_local, Args, Functs, Renderer, Splat},
+    tiled::TileGrid,
+};
+
+/// x, y, w, h of a tile in frame pixels
+type Rect = (i32, i32, i32, i32);
+
+const DONE: u8 = 0;
+const TILE: u8 = 1;
+const ACCUM: u8 = 2;
+const ITERS: u8 = 3;
+const SPLATS: u8 = 4;
+
+/// What a worker sends back for one tile
+enum TileData {
+    Accum(Vec<Color>, Vec<f64>, Vec<usize>),
+    Iters(IterData),
+    Splats(Vec<((f64, f64), Sample)>),
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn write_tile(w: &mut impl Write, rect: Rect) -> io::Result<()> {
+    w.write_all(&[TILE])?;
+    for v in [rect.0, rect.1, rect.2, rect.3] {
+        w.write_all(&(v as u32).to_le_bytes())?;
+    }
+    w.flush()
+}
+
+fn read_tile_data(r: &mut impl Read, rect: Rect) -> io::Result<TileData> {
+    let len = (rect.2 * rect.3) as usize;
+    match read_u8(r)? {
+        ACCUM => {
+            // every tile is rendered with the scene's sample count
+            let _rendered_samples = read_u64(r)?;
+            let mut raw = Vec::with_capacity(len);
+            for _ in 0..len {
+                let mut ch = [0f64; 4];
+                for v in ch.iter_mut() {
+                    *v = read_f64(r)?;
+                }
+                raw.push(Color::new(ch, ColorType::SRgba));
+            }
+            let mut weights = Vec::with_capacity(len);
+            for _ in 0..len {
+                weights.push(read_f64(r)?);
+            }
+            let count = read_u32(r)? as usize;
+            if count != 0 && count != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "tile has the wrong number of sample counts",
+                ));
+            }
+            let mut extra = Vec::with_capacity(count);
+            for _ in 0..count {
+                extra.push(read_u32(r)? as usize);
+            }
+            Ok(TileData::Accum(raw, weights, extra))
+        }
+        ITERS => {
+            let samples = read_u32(r)? as usize;
+            let mut data = Vec::with_capacity(len * samples);
+            for _ in 0..(len * samples) {
+                data.push(Sample::read(r)?);
+            }
+            Ok(TileData::Iters(IterData::new(
+                rect.2, rect.3, samples, data,
+            )))
+        }
+        SPLATS => {
+            let count = read_u32(r)? as usize;
+            let mut splats = Vec::with_capacity(count);
+            for _ in 0..count {
+                let pos = (read_f64(r)?, read_f64(r)?);
+                splats.push((pos, Sample::read(r)?));
+            }
+            Ok(TileData::Splats(splats))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected message from worker",
+        )),
+    }
+}
+
+/// Hands tiles to one worker until the queue is drained, a tile whose
+/// worker drops the connection or times out goes back in the queue for
+/// someone else
+fn serve_worker(
+    stream: TcpStream,
+    timeout: Duration,
+    scene: Arc<String>,
+    queue: Arc<Mutex<VecDeque<Rect>>>,
+    finished: Arc<AtomicUsize>,
+    total: usize,
+    results: mpsc::Sender<(Rect, TileData)>,
+) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    if stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .is_err()
+    {
+        return;
+    }
+    let mut r = BufReader::new(stream.try_clone().unwrap());
+    let mut w = BufWriter::new(stream);
+    let hello = w
+        .write_all(&(scene.len() as u32).to_le_bytes())
+        .and_then(|_| w.write_all(scene.as_bytes()))
+        .and_then(|_| w.flush());
+    if hello.is_err() {
+        return;
+    }
+    println!("worker {} connected", peer);
+    loop {
+        let rect = queue.lock().unwrap().pop_front();
+        let rect = match rect {
+            Some(rect) => rect,
+            None if finished.load(Ordering::SeqCst) >= total => {
+                let _ = w.write_all(&[DONE]).and_then(|_| w.flush());
+                return;
+            }
+            None => {
+                // tiles are still out with other workers, one of them may die
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        match write_tile(&mut w, rect).and_then(|_| read_tile_data(&mut r, rect)) {
+            Ok(data) => {
+                if results.send((rect, data)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                println!("worker {} lost ({}), requeueing its tile", peer, e);
+                queue.lock().unwrap().push_back(rect);
+                return;
+            }
+        }
+    }
+}
+
+/// Splits the frame into `--tile` sized tiles (256 when unset), hands them
+/// to workers connecting on `addr` and gathers the results into one renderer
+pub fn coordinate(args: &Args, functs: &Functs, addr: impl ToSocketAddrs) -> io::Result<Renderer> {
+    let listener = TcpListener::bind(addr)?;
+    println!("waiting for workers on {}", listener.local_addr()?);
+    coordinate_on(args, functs, listener)
+}
+
+/// `coordinate` with workers connecting to `listener`
+pub fn coordinate_on(args: &Args, functs: &Functs, listener: TcpListener) -> io::Result<Renderer> {
+    let grid = TileGrid::new(
+        args.width,
+        args.height,
+        if args.tile > 0 { args.tile } else { 256 },
+    );
+    let mut queue = VecDeque::with_capacity(grid.len());
+    for ty in 0..grid.rows {
+        for tx in 0..grid.cols {
+            queue.push_back(grid.rect(tx, ty));
+        }
+    }
+    let queue = Arc::new(Mutex::new(queue));
+    let finished = Arc::new(AtomicUsize::new(0));
+    let scene = Arc::new(args.to_scene());
+    let (sender, results) = mpsc::channel();
+    {
+        let (queue, finished) = (queue.clone(), finished.clone());
+        let total = grid.len();
+        let timeout = Duration::from_secs(args.worker_timeout.max(1));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (scene, queue, finished, sender) = (
+                    scene.clone(),
+                    queue.clone(),
+                    finished.clone(),
+                    sender.clone(),
+                );
+                thread::spawn(move || {
+                    serve_worker(stream, timeout, scene, queue, finished, total, sender)
+                });
+            }
+        });
+    }
+
+    let mut rend = Renderer::new(args.clone(), functs.clone());
+    for n in 0..grid.len() {
+        let ((x, y, w, h), data) = results
+            .recv()
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        match data {
+            TileData::Accum(raw, weights, extra) => {
+                for (i, (e, weight)) in raw.into_iter().zip(weights).enumerate() {
+                    let (px, py) = (x as usize + i % w as usize, y as usize + i / w as usize);
+                    rend.raw[py][px] = e;
+                    if let Some(row) = rend.weights.get_mut(py) {
+                        row[px] = weight;
+                    }
+                    if let (Some(row), Some(n)) = (rend.extra_samples.get_mut(py), extra.get(i)) {
+                        row[px] = *n;
+                    }
+                }
+            }
+            TileData::Iters(data) => {
+                for (i, px) in data.data.chunks(data.samples).enumerate() {
+                    let (lx, ly) = block_local(i, w as usize, h as usize);
+                    let (px_x, px_y) = (x as usize + lx, y as usize + ly);
+                    rend.raw[px_y][px_x] = px
+                        .iter()
+                        .fold(Color::new([0.0; 4], ColorType::SRgba), |out, e| {
+                            out + rend.shade(e)
+                        });
+                    if let Some(row) = rend.weights.get_mut(px_y) {
+                        row[px_x] = data.samples as f64;
+                    }
+                }
+            }
+            TileData::Splats(splats) => {
+                let splats: Vec<Splat> = splats
+                    .iter()
+                    .map(|(pos, e)| (*pos, rend.shade(e)))
+                    .collect();
+                rend.splat(&splats);
+            }
+        }
+        finished.fetch_add(1, Ordering::SeqCst);
+        println!("tile {}/{} done", n + 1, grid.len());
+    }
+    rend.rendered_samples = args.samples;
+    Ok(rend)
+}
+
+/// Connects to a coordinator and renders whatever tiles it hands out,
+/// returns once the coordinator says there's nothing left
+pub fn work(addr: impl ToSocketAddrs) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+    let mut scene = vec![0u8; read_u32(&mut r)? as usize];
+    r.read_exact(&mut scene)?;
+    let args = Args::from_scene(&String::from_utf8_lossy(&scene))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let functs = Functs::from_args(&args);
+    loop {
+        match read_u8(&mut r) {
+            Ok(TILE) => {}
+            Ok(_) => return Ok(()),
+            // the coordinator may exit before telling every worker it's done
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut rect = [0i32; 4];
+        for v in rect.iter_mut() {
+            *v = read_u32(&mut r)? as i32;
+        }
+        let [x, y, tw, th] = rect;
+        let mut rend = Renderer::tile(args.clone(), functs.clone(), x, y, tw, th);
+        if args.send_iters && rend.splatting() {
+            rend.trace_interior();
+            let splats = sample_splats(&rend, args.samples);
+            w.write_all(&[SPLATS])?;
+            w.write_all(&(splats.len() as u32).to_le_bytes())?;
+            for ((px, py), e) in splats.iter() {
+                w.write_all(&px.to_le_bytes())?;
+                w.write_all(&py.to_le_bytes())?;
+                e.write(&mut w)?;
+            }
+        } else if args.send_iters {
+            let data = rend.record_samples(args.samples, false);
+            w.write_all(&[ITERS])?;
+            w.write_all(&(data.samples as u32).to_le_bytes())?;
+            for e in data.data.iter() {
+                e.write(&mut w)?;
+            }
+        } else {
+            rend.render_samples(args.samples, false);
+            if args.noise > 0.0 {
+                rend.render_adaptive(args.adaptive_step, args.noise, args.max_samples);
+            }
+            w.write_all(&[ACCUM])?;
+            w.write_all(&(rend.rendered_samples as u64).to_le_bytes())?;
+            for row in rend.raw.iter() {
+                for e in row.iter() {
+                    for v in e.ch {
+                        w.write_all(&v.to_le_bytes())?;
+                    }
+                }
+            }
+            for py in 0..th as usize {
+                for px in 0..tw as usize {
+                    w.write_all(&rend.weight(px, py).to_le_bytes())?;
+                }
+            }
+            let count = rend.extra_samples.len() * tw as usize;
+            w.write_all(&(count as u32).to_le_bytes())?;
+            for row in rend.extra_samples.iter() {
+                for e in row.iter() {
+                    w.write_all(&(*e as u32).to_le_bytes())?;
+                }
+            }
+        }
+        w.flush()?;
+    }
+}
+
+/// Every sample of `rend`'s pixels with where it landed, what `--send-iters`
+/// sends when the coordinator has to splat them itself. Samples landing in
+/// other tiles are spread over them there, so no apron is needed
+fn sample_splats(rend: &Renderer, samples: usize) -> Vec<((f64, f64), Sample)> {
+    let d = rend.pixel_delta();
+    (0..rend.width * rend.height)
+        .into_par_iter()
+        .map(|i| rend.coords(i))
+        .flat_map_iter(|(x, y)| {
+            let offsets = rend.offsets(x, y, rend.rendered_samples, samples);
+            let landed: Vec<(f64, f64)> = offsets.iter().map(|o| rend.landing(x, y, *o)).collect();
+            landed.into_iter().zip(rend.sample_batch(x, y, d, &offsets))
+        })
+        .collect()
+}
@@ -0,0 +1,315 @@
+// fracgen
+// Terminal explorer
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    io::{self, Stdout, Write},
+    path,
+    time::Duration,
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute, queue,
+    style::{self, Print},
+    terminal::{self, ClearType},
+};
+use num::complex::Complex;
+
+use crate::{
+    formula::FORMULAS,
+    renderer::{Args, Functs, Renderer},
+};
+
+type Cf64 = Complex<f64>;
+
+/// Samples per pixel the preview refines to while no key is pressed
+const MAX_SAMPLES: usize = 16;
+/// The first pass of a new view is this many times coarser than the terminal
+const COARSE: i32 = 4;
+/// Fraction of the view one pan key moves
+const PAN: f64 = 0.1;
+const ZOOM_STEP: f64 = 1.5;
+const IDLE: Duration = Duration::from_millis(250);
+
+const HELP: &str =
+    "arrows pan  +/- zoom  [/] limit  f fractal  c color  i julia  s save  r render  q quit";
+
+enum Action {
+    None,
+    Redraw,
+    Save,
+    Render,
+    Quit,
+}
+
+/// The explorer's view, `args` keeps the full size render settings
+struct View {
+    args: Args,
+    /// origin and zoom of the mandelbrot view a julia toggle came from
+    parent: Option<(Cf64, f64)>,
+    message: String,
+}
+
+impl View {
+    fn key(&mut self, code: KeyCode) -> Action {
+        let step = PAN * 2.0 / self.args.zoom;
+        let args = &mut self.args;
+        match code {
+            KeyCode::Left | KeyCode::Char('h') => args.origin.re -= step,
+            KeyCode::Right | KeyCode::Char('l') => args.origin.re += step,
+            KeyCode::Up | KeyCode::Char('k') => args.origin.im -= step,
+            KeyCode::Down | KeyCode::Char('j') => args.origin.im += step,
+            KeyCode::Char('+') | KeyCode::Char('=') => args.zoom *= ZOOM_STEP,
+            KeyCode::Char('-') => args.zoom /= ZOOM_STEP,
+            KeyCode::Char(']') => args.limit *= 2.0,
+            KeyCode::Char('[') => args.limit = (args.limit / 2.0).max(16.0),
+            KeyCode::Char('f') => {
+                let k = FORMULAS.iter().position(|f| *f == args.fractal_mode);
+                args.fractal_mode = FORMULAS[k.map_or(0, |k| k + 1) % FORMULAS.len()];
+                args.hybrid = None;
+            }
+            KeyCode::Char('c') => args.color_mode = (args.color_mode + 1) % 6,
+            KeyCode::Char('i') => {
+                if args.is_julia {
+                    // back to where the julia set was picked
+                    let (origin, zoom) = self.parent.take().unwrap_or((args.julia, 0.7));
+                    args.origin = origin;
+                    args.zoom = zoom;
+                } else {
+                    // the julia set of the point in the middle of the view
+                    self.parent = Some((args.origin, args.zoom));
+                    args.julia = args.origin;
+                    args.origin = Cf64::new(0.0, 0.0);
+                    args.zoom = 0.7;
+                }
+                args.is_julia = !args.is_julia;
+            }
+            KeyCode::Char('s') => return Action::Save,
+            KeyCode::Char('r') | KeyCode::Enter => return Action::Render,
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            _ => return Action::None,
+        }
+        Action::Redraw
+    }
+
+    /// Writes the view to the first free `{name}.scene`, `{name}_2.scene`, ...
+    fn save(&mut self) -> io::Result<()> {
+        let mut path = format!("{}.scene", self.args.name);
+        let mut n = 2;
+        while path::Path::new(&path).exists() {
+            path = format!("{}_{}.scene", self.args.name, n);
+            n += 1;
+        }
+        fs::write(&path, self.args.to_scene())?;
+        self.message = format!("saved {}", path);
+        Ok(())
+    }
+
+    fn status(&self, samples: usize) -> String {
+        let args = &self.args;
+        format!(
+            "{}{} zoom {:.3e} limit {} {} c{} spp {}  {}  {}",
+            if args.is_julia { "julia " } else { "" },
+            if args.is_julia {
+                args.julia
+            } else {
+                args.origin
+            },
+            args.zoom,
+            args.limit,
+            args.fractal_mode,
+            args.color_mode,
+            samples,
+            self.message,
+            HELP
+        )
+    }
+
+    /// A renderer for the view at `w`x`h` pixels
+    fn preview(&self, w: i32, h: i32) -> Renderer {
+        let args = Args {
+            width: w,
+            height: h,
+            checkpoint: None,
+            accum: None,
+            noise: 0.0,
+            ..self.args.clone()
+        };
+        let functs = Functs::from_args(&args);
+        Renderer::new(args, functs)
+    }
+}
+
+/// Previews `args` in the terminal with half-block characters, two pixels per
+/// cell. Returns the view's settings when a full render was asked for
+pub fn explore(args: Args) -> io::Result<Option<Args>> {
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = run(&mut out, args);
+    execute!(
+        out,
+        style::ResetColor,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    )?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn run(out: &mut Stdout, args: Args) -> io::Result<Option<Args>> {
+    let mut view = View {
+        args,
+        parent: None,
+        message: String::new(),
+    };
+    // None means the view changed and refinement starts over
+    let mut rend: Option<Renderer> = None;
+    loop {
+        let (cols, rows) = terminal::size()?;
+        let (w, h) = (cols as i32, (rows as i32 - 1).max(1) * 2);
+        match rend.as_mut() {
+            None => {
+                let mut coarse = view.preview((w / COARSE).max(1), (h / COARSE).max(1));
+                coarse.render_samples(1, false);
+                coarse.process_image();
+                draw(out, &coarse, w, h, &view.status(1))?;
+                rend = Some(view.preview(w, h));
+            }
+            Some(fine) if fine.rendered_samples < MAX_SAMPLES => {
+                fine.render_samples(1, false);
+                fine.process_image();
+                draw(out, fine, w, h, &view.status(fine.rendered_samples))?;
+            }
+            Some(_) => {}
+        }
+        let refining = !matches!(&rend, Some(r) if r.rendered_samples >= MAX_SAMPLES);
+        if !event::poll(if refining { Duration::ZERO } else { IDLE })? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                view.message.clear();
+                match view.key(key.code) {
+                    Action::None => {}
+                    Action::Redraw => rend = None,
+                    Action::Save => {
+                        if let Err(e) = view.save() {
+                            view.message = format!("couldn't save: {}", e);
+                        }
+                        let samples = rend.as_ref().map_or(0, |r| r.rendered_samples);
+                        draw_status(out, rows, &view.status(samples))?;
+                    }
+                    Action::Render => return Ok(Some(view.args)),
+                    Action::Quit => return Ok(None),
+                }
+            }
+            Event::Resize(..) => rend = None,
+            _ => {}
+        }
+    }
+}
+
+/// Draws `rend`'s image stretched to `w`x`h` pixels, then the status line
+fn draw(out: &mut Stdout, rend: &Renderer, w: i32, h: i32, status: &str) -> io::Result<()> {
+    let (iw, ih) = (rend.image.width(), rend.image.height());
+    let pixel = |x: i32, y: i32| {
+        let p = rend
+            .image
+            .get_pixel(x as u32 * iw / w as u32, y as u32 * ih / h as u32)
+            .0;
+        style::Color::Rgb {
+            r: p[0],
+            g: p[1],
+            b: p[2],
+        }
+    };
+    for row in 0..h / 2 {
+        queue!(out, cursor::MoveTo(0, row as u16))?;
+        for x in 0..w {
+            queue!(
+                out,
+                style::SetForegroundColor(pixel(x, row * 2)),
+                style::SetBackgroundColor(pixel(x, row * 2 + 1)),
+                Print('▀')
+            )?;
+        }
+    }
+    draw_status(out, (h / 2 + 1) as u16, status)
+}
+
+fn draw_status(out: &mut Stdout, rows: u16, status: &str) -> io::Result<()> {
+    let cols = terminal::size()?.0 as usize;
+    queue!(
+        out,
+        cursor::MoveTo(0, rows - 1),
+        style::ResetColor,
+        Print(status.chars().take(cols).collect::<String>()),
+        terminal::Clear(ClearType::UntilNewLine)
+    )?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view() -> View {
+        View {
+            args: Args::new(),
+            parent: None,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn julia_toggle_comes_back_to_the_parent_view() {
+        let mut view = view();
+        view.key(KeyCode::Right);
+        view.key(KeyCode::Char('+'));
+        let (origin, zoom) = (view.args.origin, view.args.zoom);
+        view.key(KeyCode::Char('i'));
+        assert!(view.args.is_julia);
+        assert_eq!(view.args.julia, origin);
+        view.key(KeyCode::Char('i'));
+        assert!(!view.args.is_julia);
+        assert_eq!((view.args.origin, view.args.zoom), (origin, zoom));
+    }
+
+    #[test]
+    fn formula_key_cycles_through_every_formula() {
+        let mut view = view();
+        let start = view.args.fractal_mode;
+        for _ in 0..FORMULAS.len() - 1 {
+            view.key(KeyCode::Char('f'));
+            assert_ne!(view.args.fractal_mode, start);
+        }
+        view.key(KeyCode::Char('f'));
+        assert_eq!(view.args.fractal_mode, start);
+    }
+
+    #[test]
+    fn limit_never_drops_below_16() {
+        let mut view = view();
+        for _ in 0..20 {
+            view.key(KeyCode::Char('['));
+        }
+        assert_eq!(view.args.limit, 16.0);
+    }
+}
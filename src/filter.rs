@@ -0,0 +1,163 @@
+// fracgen
+// Reconstruction filters
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+/// How much a sample counts towards a pixel depending on its distance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            "lanczos" => Ok(Filter::Lanczos),
+            _ => Err(format!(
+                "unknown filter {}, expected box, tent, gaussian, mitchell or lanczos",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+            Filter::Lanczos => "lanczos",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-8 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter {
+    /// 1D weight at distance `x` (in pixels) for a filter reaching `radius` pixels
+    pub fn weight_1d(&self, x: f64, radius: f64) -> f64 {
+        match self {
+            // half open so a sample on a pixel edge only lands in one pixel
+            Filter::Box => (-radius <= x && x < radius) as u8 as f64,
+            Filter::Tent => (1.0 - x.abs() / radius).max(0.0),
+            Filter::Gaussian => {
+                let sigma = radius / 3.0;
+                let g = |x: f64| (-(x * x) / (2.0 * sigma * sigma)).exp();
+                (g(x) - g(radius)).max(0.0)
+            }
+            Filter::Mitchell => {
+                // B = C = 1/3, defined on [-2, 2]
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = (2.0 * x / radius).abs();
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos => {
+                if x.abs() >= radius {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / radius)
+                }
+            }
+        }
+    }
+
+    /// Separable 2D weight of a sample `(dx, dy)` pixels away
+    pub fn weight(&self, dx: f64, dy: f64, radius: f64) -> f64 {
+        self.weight_1d(dx, radius) * self.weight_1d(dy, radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::Mitchell,
+        Filter::Lanczos,
+    ];
+
+    #[test]
+    fn names_round_trip() {
+        for filter in FILTERS {
+            assert_eq!(filter.to_string().parse::<Filter>(), Ok(filter));
+        }
+        assert!("sinc".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn box_edges_are_half_open() {
+        assert_eq!(Filter::Box.weight_1d(-0.5, 0.5), 1.0);
+        assert_eq!(Filter::Box.weight_1d(0.5, 0.5), 0.0);
+    }
+
+    #[test]
+    fn nothing_lands_beyond_the_radius() {
+        for filter in FILTERS {
+            for radius in [0.5, 1.5, 2.0] {
+                for x in [radius, radius + 0.25, -radius - 1.0] {
+                    assert_eq!(filter.weight(x, 0.0, radius), 0.0, "{} {}", filter, x);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weights_peak_at_the_sample() {
+        for filter in FILTERS {
+            let peak = filter.weight(0.0, 0.0, 1.5);
+            assert!(peak > 0.0);
+            for x in [0.3, 0.7, 1.2] {
+                assert!(filter.weight(x, 0.0, 1.5) <= peak, "{} {}", filter, x);
+                assert_eq!(filter.weight_1d(x, 1.5), filter.weight_1d(-x, 1.5));
+            }
+        }
+    }
+}
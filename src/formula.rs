@@ -0,0 +1,601 @@
+// fracgen
+// Formula library
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The escape time formulas `--fractal-mode` picks by name. `c` is the pixel,
+// or `--julia` with `-i`, `w` the previous z and `a` the formula's parameter
+// from `--params` (re, im). Each step reads and updates a `State`, which also
+// has the step count and values a formula keeps for itself:
+//     mandelbrot     z^2 + c
+//     multibrot      z^a + c, any real or complex power (3)
+//     tricorn        conj(z)^2 + c, the mandelbar
+//     burningship    (|x| + i|y|)^2 + c
+//     celtic         |x^2 - y^2| + 2xyi + c
+//     buffalo        |x^2 - y^2| + 2|xy|i + c
+//     perpendicular  (x - i|y|)^2 + c, the perpendicular burning ship
+//     phoenix        z^2 + c + a w (-0.5)
+//     magnet1        ((z^2 + c - 1) / (2z + c - 2))^2
+//     magnet2        ((z^3 + 3(c - 1)z + (c - 1)(c - 2))
+//                     / (3z^2 + 3(c - 2)z + (c - 1)(c - 2) + 1))^2
+//     lambda         c z (1 - z)
+//     manowar        z^2 + w + c
+//     spider         z^2 + k, then k/2 + z for k, k starts at c
+//     collatz        (2 + 7z - (2 + 5z) cos(pi z)) / 4 + c
+//     sine           c sin(z)
+//     cosh           c cosh(z)
+//     exp            c e^z
+//     hybrid         z^2 + c, then a burning ship step and two more z^2 + c
+//     zcpow          (z c)^(z / c) + z / c
+// `fractal_mode` 0 to 3 from older scenes are mandelbrot, burningship, hybrid
+// and zcpow. Mandelbrot style orbits start at the formula's critical point,
+// offset by `--z-init`.
+//
+// `--hybrid` iterates a sequence of formulas instead, each repeated its count
+// of steps, `mandelbrot*2,burningship` goes mandelbrot, mandelbrot, burning
+// ship, mandelbrot, ... Every step is one iteration and takes the derivative
+// of its own formula.
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use num::complex::Complex;
+
+type Cf64 = Complex<f64>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Formula {
+    Mandelbrot,
+    Multibrot,
+    Tricorn,
+    BurningShip,
+    Celtic,
+    Buffalo,
+    Perpendicular,
+    Phoenix,
+    Magnet1,
+    Magnet2,
+    Lambda,
+    Manowar,
+    Spider,
+    Collatz,
+    Sine,
+    Cosh,
+    Exp,
+    Hybrid,
+    ZcPow,
+}
+
+/// Every formula, in the order the explorer cycles through them
+pub const FORMULAS: [Formula; 19] = [
+    Formula::Mandelbrot,
+    Formula::Multibrot,
+    Formula::Tricorn,
+    Formula::BurningShip,
+    Formula::Celtic,
+    Formula::Buffalo,
+    Formula::Perpendicular,
+    Formula::Phoenix,
+    Formula::Magnet1,
+    Formula::Magnet2,
+    Formula::Lambda,
+    Formula::Manowar,
+    Formula::Spider,
+    Formula::Collatz,
+    Formula::Sine,
+    Formula::Cosh,
+    Formula::Exp,
+    Formula::Hybrid,
+    Formula::ZcPow,
+];
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Formula::Mandelbrot => "mandelbrot",
+            Formula::Multibrot => "multibrot",
+            Formula::Tricorn => "tricorn",
+            Formula::BurningShip => "burningship",
+            Formula::Celtic => "celtic",
+            Formula::Buffalo => "buffalo",
+            Formula::Perpendicular => "perpendicular",
+            Formula::Phoenix => "phoenix",
+            Formula::Magnet1 => "magnet1",
+            Formula::Magnet2 => "magnet2",
+            Formula::Lambda => "lambda",
+            Formula::Manowar => "manowar",
+            Formula::Spider => "spider",
+            Formula::Collatz => "collatz",
+            Formula::Sine => "sine",
+            Formula::Cosh => "cosh",
+            Formula::Exp => "exp",
+            Formula::Hybrid => "hybrid",
+            Formula::ZcPow => "zcpow",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Formula {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        // the numbers `fractal_mode` used to take
+        match string {
+            "0" => return Ok(Formula::Mandelbrot),
+            "1" => return Ok(Formula::BurningShip),
+            "2" => return Ok(Formula::Hybrid),
+            "3" => return Ok(Formula::ZcPow),
+            _ => {}
+        }
+        let string = string.to_lowercase();
+        match string.as_str() {
+            "mandelbar" => return Ok(Formula::Tricorn),
+            "magnet" => return Ok(Formula::Magnet1),
+            _ => {}
+        }
+        FORMULAS
+            .iter()
+            .find(|f| f.to_string() == string)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<String> = FORMULAS.iter().map(Formula::to_string).collect();
+                format!(
+                    "unknown formula {}, expected one of {}",
+                    string,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Everything an orbit carries from one step to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct State {
+    pub z: Cf64,
+    /// the z before `z`
+    pub prev: Cf64,
+    /// derivative of `z` by `c`
+    pub der: Cf64,
+    /// steps taken so far
+    pub n: usize,
+    /// derivative of what the orbit adds, 1 when it's the pixel and 0 for
+    /// julia sets, whose derivative is by the starting z
+    pub dc: Cf64,
+    /// whatever else the formula keeps, the derivative of `prev` for the
+    /// ones that add it and the moving `k` and its derivative for spider
+    pub aux: [Cf64; 2],
+}
+
+/// `z^a`, exact for whole real powers
+fn pow(z: Cf64, a: Cf64) -> Cf64 {
+    if a.im != 0.0 {
+        z.powc(a)
+    } else if a.re.fract() == 0.0 && a.re.abs() <= i32::MAX as f64 {
+        z.powi(a.re as i32)
+    } else {
+        z.powf(a.re)
+    }
+}
+
+/// `n / d` squared and its derivative, from the derivatives of `n` and `d`
+fn square_quotient(n: Cf64, d: Cf64, dn: Cf64, dd: Cf64) -> (Cf64, Cf64) {
+    let w = n / d;
+    (w * w, w * 2.0 * (dn * d - n * dd) / (d * d))
+}
+
+impl Formula {
+    /// Parameters used when `--params` isn't given
+    pub fn default_params(&self) -> Vec<f64> {
+        match self {
+            Formula::Multibrot => vec![3.0],
+            Formula::Phoenix => vec![-0.5, 0.0],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Orbits farther than this from 0 are taken to escape, `--bail` is its
+    /// square unless given
+    pub fn radius(&self) -> f64 {
+        match self {
+            Formula::Magnet1 | Formula::Magnet2 => 100.0,
+            Formula::Collatz => 100.0,
+            Formula::Sine | Formula::Cosh | Formula::Exp => 50.0,
+            _ => 8.0,
+        }
+    }
+
+    /// The degree in z for the polynomial formulas (ignoring folds and the
+    /// previous z), `None` for the rational and transcendental ones
+    pub fn degree(&self, a: Cf64) -> Option<f64> {
+        match self {
+            Formula::Multibrot => Some(a.re),
+            Formula::Magnet1 | Formula::Magnet2 => None,
+            Formula::Collatz | Formula::Sine | Formula::Cosh | Formula::Exp => None,
+            Formula::ZcPow => None,
+            // each hybrid step is made of four squarings
+            Formula::Hybrid => Some(16.0),
+            _ => Some(2.0),
+        }
+    }
+
+    /// Where the orbit of pixel `c` starts without `--z-init`, a critical
+    /// point so the set is connected where it should be
+    pub fn critical(&self, c: Cf64) -> Cf64 {
+        match self {
+            Formula::Lambda => Cf64::new(0.5, 0.0),
+            Formula::Sine => Cf64::new(PI / 2.0, 0.0),
+            Formula::Manowar => c,
+            _ => Cf64::new(0.0, 0.0),
+        }
+    }
+
+    /// The state an orbit starting at `z` begins in, `c` is what it adds,
+    /// the pixel itself unless `julia`
+    pub fn state(&self, z: Cf64, c: Cf64, julia: bool) -> State {
+        let one = Cf64::new(1.0, 0.0);
+        let dc = match julia {
+            true => Cf64::new(0.0, 0.0),
+            false => one,
+        };
+        State {
+            z,
+            prev: z,
+            der: one,
+            n: 0,
+            dc,
+            aux: match self {
+                Formula::Spider => [c, dc],
+                _ => [one, Cf64::new(0.0, 0.0)],
+            },
+        }
+    }
+
+    /// Takes `s` one step further
+    pub fn step(&self, s: &mut State, c: Cf64, a: Cf64) {
+        let der = self.derivative(s, c, a);
+        let z = self.next(s, c, a);
+        match self {
+            Formula::Phoenix | Formula::Manowar => s.aux[0] = s.der,
+            Formula::Spider => {
+                s.aux[0] = s.aux[0] / 2.0 + z;
+                s.aux[1] = s.aux[1] / 2.0 + der;
+            }
+            _ => {}
+        }
+        s.prev = s.z;
+        s.z = z;
+        s.der = der;
+        s.n += 1;
+    }
+
+    /// The z after the one of `s`
+    fn next(&self, s: &State, c: Cf64, a: Cf64) -> Cf64 {
+        let (z, w) = (s.z, s.prev);
+        let one = Cf64::new(1.0, 0.0);
+        match self {
+            Formula::Mandelbrot => z * z + c,
+            Formula::Multibrot => pow(z, a) + c,
+            Formula::Tricorn => z.conj() * z.conj() + c,
+            Formula::BurningShip => {
+                Cf64::new(z.re.abs(), z.im.abs()) * Cf64::new(z.re.abs(), z.im.abs()) + c
+            }
+            Formula::Celtic => {
+                let s = z * z;
+                Cf64::new(s.re.abs(), s.im) + c
+            }
+            Formula::Buffalo => {
+                let s = z * z;
+                Cf64::new(s.re.abs(), s.im.abs()) + c
+            }
+            Formula::Perpendicular => {
+                let f = Cf64::new(z.re, -z.im.abs());
+                f * f + c
+            }
+            Formula::Phoenix => z * z + c + a * w,
+            Formula::Magnet1 => {
+                let w = (z * z + c - one) / (z * 2.0 + c - 2.0);
+                w * w
+            }
+            Formula::Magnet2 => {
+                let (c1, c2) = (c - one, c - 2.0);
+                let w = (z * z * z + z * c1 * 3.0 + c1 * c2)
+                    / (z * z * 3.0 + z * c2 * 3.0 + c1 * c2 + one);
+                w * w
+            }
+            Formula::Lambda => c * z * (one - z),
+            Formula::Manowar => z * z + w + c,
+            Formula::Spider => z * z + s.aux[0],
+            Formula::Collatz => (z * 7.0 + 2.0 - (z * 5.0 + 2.0) * (z * PI).cos()) / 4.0 + c,
+            Formula::Sine => c * z.sin(),
+            Formula::Cosh => c * z.cosh(),
+            Formula::Exp => c * z.exp(),
+            Formula::Hybrid => {
+                let mut tz = z * z + c;
+                tz = Cf64::new(tz.re.abs(), tz.im.abs()) * Cf64::new(tz.re.abs(), tz.im.abs()) + c;
+                tz = tz * tz + c;
+                tz = tz * tz + c;
+                tz
+            }
+            Formula::ZcPow => (z * c).powc(z / c) + (z / c),
+        }
+    }
+
+    /// The derivative by the pixel of the z after the one of `s`, `s.dc` is
+    /// how much `c` moves with it. Holomorphic formulas get it exactly, folds
+    /// are left out so the quadratic ones all use `2 z der + dc`
+    fn derivative(&self, s: &State, c: Cf64, a: Cf64) -> Cf64 {
+        let (z, der, dc) = (s.z, s.der, s.dc);
+        let one = Cf64::new(1.0, 0.0);
+        match self {
+            Formula::Multibrot => der * a * pow(z, a - one) + dc,
+            Formula::Phoenix => (der * 2.0 * z) + dc + a * s.aux[0],
+            Formula::Manowar => (der * 2.0 * z) + dc + s.aux[0],
+            Formula::Spider => (der * 2.0 * z) + s.aux[1],
+            Formula::Magnet1 => {
+                let (n, d) = (z * z + c - one, z * 2.0 + c - 2.0);
+                let (_, dz) = square_quotient(n, d, z * 2.0, Cf64::new(2.0, 0.0));
+                let (_, by_c) = square_quotient(n, d, one, one);
+                dz * der + by_c * dc
+            }
+            Formula::Magnet2 => {
+                let (c1, c2) = (c - one, c - 2.0);
+                let n = z * z * z + z * c1 * 3.0 + c1 * c2;
+                let d = z * z * 3.0 + z * c2 * 3.0 + c1 * c2 + one;
+                let (_, dz) = square_quotient(n, d, z * z * 3.0 + c1 * 3.0, z * 6.0 + c2 * 3.0);
+                // n and d change the same way with c
+                let nc = z * 3.0 + c * 2.0 - 3.0;
+                let (_, by_c) = square_quotient(n, d, nc, nc);
+                dz * der + by_c * dc
+            }
+            Formula::Lambda => c * (one - z * 2.0) * der + z * (one - z) * dc,
+            Formula::Collatz => {
+                let (s, k) = ((z * PI).sin(), (z * PI).cos());
+                (k * -5.0 + 7.0 + (z * 5.0 + 2.0) * s * PI) / 4.0 * der + dc
+            }
+            Formula::Sine => c * z.cos() * der + z.sin() * dc,
+            Formula::Cosh => c * z.sinh() * der + z.cosh() * dc,
+            Formula::Exp => c * z.exp() * der + z.exp() * dc,
+            // the legacy modes keep the rule every mode used to share
+            _ => (der * 2.0 * z) + dc,
+        }
+    }
+}
+
+/// Formulas iterated in turn, each for its count of steps
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hybrid(pub Vec<(Formula, usize)>);
+
+impl fmt::Display for Hybrid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: Vec<String> = self
+            .0
+            .iter()
+            .map(|(formula, count)| match count {
+                1 => formula.to_string(),
+                _ => format!("{}*{}", formula, count),
+            })
+            .collect();
+        write!(f, "{}", steps.join(","))
+    }
+}
+
+impl FromStr for Hybrid {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let steps = string
+            .split(',')
+            .map(|step| {
+                let (name, count) = match step.split_once('*') {
+                    Some((name, count)) => (name, count.trim()),
+                    None => (step, "1"),
+                };
+                let count: usize = count
+                    .parse()
+                    .map_err(|_| format!("bad repeat count {} in {}", count, step))?;
+                if count == 0 {
+                    return Err(format!("{} repeats 0 times", name.trim()));
+                }
+                Ok((name.trim().parse()?, count))
+            })
+            .collect::<Result<Vec<(Formula, usize)>, String>>()?;
+        Ok(Hybrid(steps))
+    }
+}
+
+impl Hybrid {
+    /// The formula of every step of one pass through the sequence
+    pub fn steps(&self) -> Vec<Formula> {
+        self.0
+            .iter()
+            .flat_map(|(formula, count)| std::iter::repeat_n(*formula, *count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functs};
+
+    /// The derivative one step of `formula` gives against central differences
+    /// of the step along z and c together, or along z alone for julia sets
+    fn check_derivative(formula: Formula, julia: bool) {
+        let a = Cf64::new(
+            formula.default_params().first().copied().unwrap_or(0.0),
+            0.0,
+        );
+        let (z, c) = (Cf64::new(0.3, -0.2), Cf64::new(-0.4, 0.5));
+        let dc = if julia { 0.0 } else { 1.0 };
+        let next = |t: f64| {
+            let mut s = formula.state(z + t, c + t * dc, julia);
+            formula.step(&mut s, c + t * dc, a);
+            s
+        };
+        let h = 1e-6;
+        let numeric = (next(h).z - next(-h).z) / (2.0 * h);
+        let der = next(0.0).der;
+        assert!(
+            (der - numeric).norm() < 1e-6 * der.norm().max(1.0),
+            "{} {}: {} {}",
+            formula,
+            julia,
+            der,
+            numeric
+        );
+    }
+
+    #[test]
+    fn names_and_mode_numbers_parse() {
+        for formula in FORMULAS {
+            assert_eq!(formula.to_string().parse::<Formula>(), Ok(formula));
+        }
+        assert_eq!("1".parse::<Formula>(), Ok(Formula::BurningShip));
+        assert_eq!("Mandelbar".parse::<Formula>(), Ok(Formula::Tricorn));
+        assert!("mandelbulb".parse::<Formula>().is_err());
+    }
+
+    #[test]
+    fn derivatives_match_differences() {
+        for formula in [
+            Formula::Mandelbrot,
+            Formula::Multibrot,
+            Formula::Magnet1,
+            Formula::Magnet2,
+            Formula::Lambda,
+            Formula::Collatz,
+            Formula::Sine,
+            Formula::Cosh,
+            Formula::Exp,
+        ] {
+            check_derivative(formula, false);
+            check_derivative(formula, true);
+        }
+    }
+
+    #[test]
+    fn multi_state_derivatives_match_differences() {
+        for formula in [Formula::Phoenix, Formula::Manowar, Formula::Spider] {
+            check_derivative(formula, false);
+            check_derivative(formula, true);
+        }
+    }
+
+    #[test]
+    fn multi_state_formulas_carry_their_state() {
+        let (z, c, a) = (
+            Cf64::new(0.3, -0.2),
+            Cf64::new(-0.4, 0.5),
+            Cf64::new(-0.5, 0.0),
+        );
+        // phoenix adds a times the z before
+        let mut s = Formula::Phoenix.state(z, c, false);
+        Formula::Phoenix.step(&mut s, c, a);
+        Formula::Phoenix.step(&mut s, c, a);
+        let z1 = z * z + c + a * z;
+        assert!((s.z - (z1 * z1 + c + a * z)).norm() < 1e-15);
+        assert_eq!(s.prev, z1);
+        // spider's k halves and takes in the new z
+        let mut s = Formula::Spider.state(z, c, false);
+        Formula::Spider.step(&mut s, c, a);
+        assert_eq!(s.z, z * z + c);
+        assert_eq!(s.aux[0], c / 2.0 + s.z);
+    }
+
+    #[test]
+    fn hybrids_round_trip() {
+        let hybrid: Hybrid = "mandelbrot*2, burningship,sine*3".parse().unwrap();
+        assert_eq!(
+            hybrid.0,
+            [
+                (Formula::Mandelbrot, 2),
+                (Formula::BurningShip, 1),
+                (Formula::Sine, 3)
+            ]
+        );
+        assert_eq!(hybrid.to_string(), "mandelbrot*2,burningship,sine*3");
+        assert_eq!(hybrid.to_string().parse::<Hybrid>(), Ok(hybrid.clone()));
+        assert_eq!(hybrid.steps().len(), 6);
+        assert_eq!(hybrid.steps()[2], Formula::BurningShip);
+    }
+
+    #[test]
+    fn broken_hybrids_are_refused() {
+        for text in ["mandelbrot*0", "mandelbrot*x", "mandelbrot,nothing", ""] {
+            assert!(text.parse::<Hybrid>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn hybrid_steps_take_turns() {
+        let args = Args {
+            hybrid: Some("mandelbrot,tricorn*2".parse().unwrap()),
+            ..Args::new()
+        };
+        let functs = Functs::from_args(&args);
+        let order: Vec<Formula> = (0..6).map(|n| functs.formula(n)).collect();
+        assert_eq!(
+            order,
+            [
+                Formula::Mandelbrot,
+                Formula::Tricorn,
+                Formula::Tricorn,
+                Formula::Mandelbrot,
+                Formula::Tricorn,
+                Formula::Tricorn
+            ]
+        );
+        // no kernel or bulb test for sequences
+        assert!(functs.kernel.is_none());
+    }
+
+    #[test]
+    fn bail_is_the_widest_radius_squared() {
+        let functs = Functs::from_args(&Args::new());
+        assert_eq!(functs.bail, 64.0);
+        let args = Args {
+            hybrid: Some("mandelbrot,sine".parse().unwrap()),
+            ..Args::new()
+        };
+        assert_eq!(Functs::from_args(&args).bail, 2500.0);
+        let args = Args {
+            bail: Some(4.0),
+            ..Args::new()
+        };
+        assert_eq!(Functs::from_args(&args).bail, 4.0);
+    }
+
+    #[test]
+    fn degrees_follow_the_sequence() {
+        let degree = |args: Args| Functs::from_args(&args).degree;
+        assert_eq!(degree(Args::new()), Some(2.0));
+        let multibrot = Args {
+            fractal_mode: Formula::Multibrot,
+            ..Args::new()
+        };
+        assert_eq!(degree(multibrot), Some(3.0));
+        // two squarings and a cube grow like 12 every three steps
+        let args = Args {
+            hybrid: Some("mandelbrot*2,multibrot".parse().unwrap()),
+            params: vec![3.0],
+            ..Args::new()
+        };
+        let mean = degree(args).unwrap();
+        assert!((mean - 12f64.cbrt()).abs() < 1e-12, "{}", mean);
+        let args = Args {
+            hybrid: Some("mandelbrot,sine".parse().unwrap()),
+            ..Args::new()
+        };
+        assert_eq!(degree(args), None);
+    }
+}
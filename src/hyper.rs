@@ -0,0 +1,420 @@
+// fracgen
+// Hypercomplex slices
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// z^2 + c over 4D number systems, `--hyper quaternion` or `bicomplex`. The
+// frame is a plane through 4D space: pixel (x, y) is the point
+// (x, y, `--slice`) turned by `--rotate`, angles in degrees in the xy, xz,
+// xw, yz, yw and zw planes. Mandelbrot style sets add that point every step,
+// julia sets start there and add `--hyper-julia` (or `--julia` in its first
+// two components). Only the plain z^2 + c is iterated, other formulas and
+// hybrids are refused with `--hyper`. The orbit goes through the same loop
+// as the 2D one, generic over the number system, and the z and derivative
+// it ends with are projected back onto the plane so coloring and lighting
+// work like in 2D.
+
+use std::{
+    fmt,
+    ops::{Add, Mul, Sub},
+    str::FromStr,
+};
+
+use num::complex::Complex;
+
+use crate::{
+    iterdata::Sample,
+    renderer::{iterate, Args, Orbit, Renderer},
+};
+
+type Cf64 = Complex<f64>;
+
+/// A number system z^2 + c can be iterated in, with 4 real components at most
+pub trait Algebra:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Mul<f64, Output = Self>
+{
+    fn from_parts(p: [f64; 4]) -> Self;
+    fn parts(&self) -> [f64; 4];
+    fn norm_sqr(&self) -> f64;
+}
+
+impl Algebra for Cf64 {
+    fn from_parts(p: [f64; 4]) -> Self {
+        Cf64::new(p[0], p[1])
+    }
+    fn parts(&self) -> [f64; 4] {
+        [self.re, self.im, 0.0, 0.0]
+    }
+    fn norm_sqr(&self) -> f64 {
+        Complex::norm_sqr(self)
+    }
+}
+
+/// a + bi + cj + dk, with i^2 = j^2 = k^2 = ijk = -1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion(pub [f64; 4]);
+
+/// z1 + z2 j with z1, z2 complex in i and ij = ji, so j^2 = -1 and (ij)^2 = 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bicomplex(pub Cf64, pub Cf64);
+
+impl Add for Quaternion {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Quaternion(std::array::from_fn(|k| self.0[k] + o.0[k]))
+    }
+}
+
+impl Sub for Quaternion {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Quaternion(std::array::from_fn(|k| self.0[k] - o.0[k]))
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        let ([a, b, c, d], [e, f, g, h]) = (self.0, o.0);
+        Quaternion([
+            a * e - b * f - c * g - d * h,
+            a * f + b * e + c * h - d * g,
+            a * g - b * h + c * e + d * f,
+            a * h + b * g - c * f + d * e,
+        ])
+    }
+}
+
+impl Mul<f64> for Quaternion {
+    type Output = Self;
+    fn mul(self, s: f64) -> Self {
+        Quaternion(self.0.map(|v| v * s))
+    }
+}
+
+impl Algebra for Quaternion {
+    fn from_parts(p: [f64; 4]) -> Self {
+        Quaternion(p)
+    }
+    fn parts(&self) -> [f64; 4] {
+        self.0
+    }
+    fn norm_sqr(&self) -> f64 {
+        self.0.iter().map(|v| v * v).sum()
+    }
+}
+
+impl Add for Bicomplex {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Bicomplex(self.0 + o.0, self.1 + o.1)
+    }
+}
+
+impl Sub for Bicomplex {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Bicomplex(self.0 - o.0, self.1 - o.1)
+    }
+}
+
+impl Mul for Bicomplex {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        Bicomplex(self.0 * o.0 - self.1 * o.1, self.0 * o.1 + self.1 * o.0)
+    }
+}
+
+impl Mul<f64> for Bicomplex {
+    type Output = Self;
+    fn mul(self, s: f64) -> Self {
+        Bicomplex(self.0 * s, self.1 * s)
+    }
+}
+
+impl Algebra for Bicomplex {
+    fn from_parts(p: [f64; 4]) -> Self {
+        Bicomplex(Cf64::new(p[0], p[1]), Cf64::new(p[2], p[3]))
+    }
+    fn parts(&self) -> [f64; 4] {
+        [self.0.re, self.0.im, self.1.re, self.1.im]
+    }
+    fn norm_sqr(&self) -> f64 {
+        self.0.norm_sqr() + self.1.norm_sqr()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hypercomplex {
+    Quaternion,
+    Bicomplex,
+}
+
+impl FromStr for Hypercomplex {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "quaternion" => Ok(Hypercomplex::Quaternion),
+            "bicomplex" => Ok(Hypercomplex::Bicomplex),
+            _ => Err(format!(
+                "unknown number system {}, expected quaternion or bicomplex",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Hypercomplex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Hypercomplex::Quaternion => "quaternion",
+            Hypercomplex::Bicomplex => "bicomplex",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where the frame's plane lies in 4D
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slice {
+    pub algebra: Hypercomplex,
+    /// columns are where the x, y, z and w axes are turned to
+    pub rotation: [[f64; 4]; 4],
+    /// z and w of the plane before it's turned
+    pub offset: [f64; 2],
+    /// what julia sets add, `None` for mandelbrot style sets
+    pub julia: Option<[f64; 4]>,
+}
+
+/// Planes `--rotate` turns in, in order
+const PLANES: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+
+impl Slice {
+    pub fn from_args(args: &Args) -> Option<Slice> {
+        let algebra = args.hyper?;
+        let mut rotation = [[0.0; 4]; 4];
+        for (k, row) in rotation.iter_mut().enumerate() {
+            row[k] = 1.0;
+        }
+        for ((a, b), angle) in PLANES.iter().zip(args.rotate.iter()) {
+            let (s, c) = angle.to_radians().sin_cos();
+            // turns the columns a and b of every row
+            for row in rotation.iter_mut() {
+                let (x, y) = (row[*a], row[*b]);
+                row[*a] = x * c + y * s;
+                row[*b] = y * c - x * s;
+            }
+        }
+        let part = |v: &[f64], k: usize| v.get(k).copied().unwrap_or(0.0);
+        let julia = match args.hyper_julia.len() {
+            0 => [args.julia.re, args.julia.im, 0.0, 0.0],
+            _ => std::array::from_fn(|k| part(&args.hyper_julia, k)),
+        };
+        Some(Slice {
+            algebra,
+            rotation,
+            offset: [part(&args.slice, 0), part(&args.slice, 1)],
+            julia: args.is_julia.then_some(julia),
+        })
+    }
+
+    /// The 4D point of `p` on the plane
+    pub fn point(&self, p: Cf64) -> [f64; 4] {
+        let v = [p.re, p.im, self.offset[0], self.offset[1]];
+        std::array::from_fn(|r| (0..4).map(|k| self.rotation[r][k] * v[k]).sum())
+    }
+
+    /// `q` seen from the plane, its components along the turned x and y axes
+    pub fn project(&self, q: [f64; 4]) -> Cf64 {
+        let along = |k: usize| (0..4).map(|r| self.rotation[r][k] * q[r]).sum();
+        Cf64::new(along(0), along(1))
+    }
+}
+
+/// z^2 + `c` in `T`, `dc` is how much `c` moves with the pixel
+#[derive(Clone, Copy)]
+struct HyperOrbit<T> {
+    z: T,
+    der: T,
+    c: T,
+    dc: T,
+}
+
+impl<T: Algebra> Orbit for HyperOrbit<T> {
+    type Num = T;
+    fn z(&self) -> T {
+        self.z
+    }
+    fn der(&self) -> T {
+        self.der
+    }
+    fn step(&mut self) {
+        // z dz + dz z, the two don't commute in the quaternions
+        self.der = self.z * self.der + self.der * self.z + self.dc;
+        self.z = self.z * self.z + self.c;
+    }
+    fn repeats(&self, other: &Self, eps: f64) -> bool {
+        (self.z - other.z).norm_sqr() < eps
+    }
+}
+
+/// Iterates z^2 + `c` from `z` through `renderer::iterate` while `inside`
+/// says z and the sum of the derivatives so far haven't escaped, returns the
+/// sample with z and the derivative still in `T`. Julia sets take the
+/// derivative by the starting z, which `c` doesn't move, like in 2D
+pub fn orbit<T: Algebra>(
+    z: T,
+    c: T,
+    julia: bool,
+    limit: f64,
+    inside: impl Fn(T, T) -> bool,
+) -> (Sample, T, T) {
+    let one = T::from_parts([1.0, 0.0, 0.0, 0.0]);
+    let dc = match julia {
+        true => T::from_parts([0.0; 4]),
+        false => one,
+    };
+    let start = HyperOrbit { z, der: one, c, dc };
+    let (sample, end) = iterate(start, limit, |z, _, tot_der| inside(z, tot_der));
+    (sample, end.z, end.der)
+}
+
+impl Renderer {
+    /// Iterates the point of the plane at `p` in the `--hyper` algebra
+    pub fn slice_sample(&self, slice: &Slice, p: Cf64) -> Sample {
+        match slice.algebra {
+            Hypercomplex::Quaternion => self.slice_orbit::<Quaternion>(slice, p),
+            Hypercomplex::Bicomplex => self.slice_orbit::<Bicomplex>(slice, p),
+        }
+    }
+
+    fn slice_orbit<T: Algebra>(&self, slice: &Slice, p: Cf64) -> Sample {
+        let point = T::from_parts(slice.point(p));
+        let z_init = self.args.z_init;
+        let (z, c) = match slice.julia {
+            Some(julia) => (point, T::from_parts(julia)),
+            None => (T::from_parts([z_init.re, z_init.im, 0.0, 0.0]), point),
+        };
+        let (bail, derbail) = (self.functs.bail, self.args.derbail);
+        // the `--bail-mode`s of `Functions`, with the norms of the algebra
+        let inside = |z: T, tot_der: T| match self.args.bail_mode {
+            1 => z.norm_sqr().sqrt() < bail,
+            2 => (tot_der * tot_der).norm_sqr() < derbail && z.norm_sqr() * z.norm_sqr() < bail,
+            _ => z.norm_sqr() < bail,
+        };
+        let julia = slice.julia.is_some();
+        let (mut sample, z, der) = orbit(z, c, julia, self.args.limit, inside);
+        sample.z = slice.project(z.parts());
+        sample.der = slice.project(der.parts());
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Functs;
+
+    fn unit(k: usize) -> [f64; 4] {
+        std::array::from_fn(|n| (n == k) as u8 as f64)
+    }
+
+    #[test]
+    fn quaternion_units_anticommute() {
+        let [one, i, j, k] = [0, 1, 2, 3].map(|n| Quaternion(unit(n)));
+        assert_eq!(i * j, k);
+        assert_eq!(j * i, k * -1.0);
+        assert_eq!(j * k, i);
+        assert_eq!(k * i, j);
+        for u in [i, j, k] {
+            assert_eq!(u * u, one * -1.0);
+        }
+    }
+
+    #[test]
+    fn bicomplex_units_commute() {
+        let [one, i, j] = [0, 1, 2].map(|n| Bicomplex::from_parts(unit(n)));
+        let ij = i * j;
+        assert_eq!(ij, j * i);
+        assert_eq!(ij * ij, one);
+        assert_eq!(j * j, one * -1.0);
+        assert_eq!(ij.parts(), unit(3));
+    }
+
+    #[test]
+    fn complex_orbits_match_the_plane() {
+        // the same iteration over the complex numbers as the 2D renderer
+        let args = Args {
+            width: 64,
+            height: 48,
+            ..Args::new()
+        };
+        let rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        let d = rend.pixel_delta();
+        for (x, y) in [(10, 10), (20, 30), (40, 24), (60, 5)] {
+            let e = rend.sample(x, y, d, (0.5, 0.5));
+            let c = rend.point(x, y, d, (0.5, 0.5));
+            let inside = |z: Cf64, _| z.norm_sqr() < rend.functs.bail;
+            let (f, z, _) = orbit(Cf64::new(0.0, 0.0), c, false, args.limit, inside);
+            assert_eq!(e.i, f.i, "{} {}", x, y);
+            // the bulb test hands back no orbit for points inside
+            if e.i < args.limit {
+                assert!((e.z - z).norm() < 1e-9 * e.z.norm());
+            }
+        }
+    }
+
+    #[test]
+    fn julia_derivatives_are_by_the_start() {
+        let c = Quaternion([-0.2, 0.6, 0.2, 0.0]);
+        let start = Quaternion([0.1, 0.2, -0.1, 0.05]);
+        let steps = |z: Quaternion| orbit(z, c, true, 6.0, |_, _| true);
+        let (_, _, der) = steps(start);
+        // along the real axis, where z and dz commute
+        let h = 1e-6;
+        let ahead = steps(start + Quaternion(unit(0)) * h).1;
+        let behind = steps(start - Quaternion(unit(0)) * h).1;
+        let numeric = (ahead - behind) * (0.5 / h);
+        assert!(
+            (der - numeric).norm_sqr().sqrt() < 1e-5,
+            "{:?} {:?}",
+            der,
+            numeric
+        );
+    }
+
+    #[test]
+    fn julia_derivatives_match_the_plane() {
+        // 2D julia sets and complex slices take the derivative by the start
+        let args = Args {
+            width: 64,
+            height: 48,
+            is_julia: true,
+            julia: Cf64::new(-0.8, 0.156),
+            scalar: true,
+            ..Args::new()
+        };
+        let rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        let d = rend.pixel_delta();
+        for (x, y) in [(10, 10), (20, 30), (40, 24), (60, 5)] {
+            let e = rend.sample(x, y, d, (0.5, 0.5));
+            let start = rend.point(x, y, d, (0.5, 0.5));
+            let inside = |z: Cf64, _| z.norm_sqr() < rend.functs.bail;
+            let (f, _, der) = orbit(start, args.julia, true, args.limit, inside);
+            assert_eq!(e.i, f.i, "{} {}", x, y);
+            assert_eq!(e.der, der, "{} {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// fracgen
+// Iteration data type
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num::complex::Complex;
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+type Cf64 = Complex<f64>;
+
+const MAGIC: &[u8; 4] = b"FGIT";
+const VERSION: u32 = 3;
+/// Bytes of the magic and the four header words
+const HEADER: u64 = 20;
+/// Bytes of one stored `Sample`
+const SAMPLE_BYTES: u64 = 6 * 8 + 4;
+
+/// Everything `color_funct` needs to know about one escaped (or trapped) sample
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub i: f64,
+    pub s: f64,
+    pub z: Cf64,
+    pub der: Cf64,
+    /// Length of the cycle the orbit fell into, 0 if it escaped or none was found
+    pub period: u32,
+}
+
+impl Sample {
+    pub(crate) fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        for v in [
+            self.i,
+            self.s,
+            self.z.re,
+            self.z.im,
+            self.der.re,
+            self.der.im,
+        ] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.write_all(&self.period.to_le_bytes())
+    }
+
+    pub(crate) fn read(r: &mut impl Read) -> io::Result<Sample> {
+        let mut v = [0f64; 6];
+        for e in v.iter_mut() {
+            *e = read_f64(r)?;
+        }
+        Ok(Sample {
+            i: v[0],
+            s: v[1],
+            z: Cf64::new(v[2], v[3]),
+            der: Cf64::new(v[4], v[5]),
+            period: read_u32(r)?,
+        })
+    }
+}
+
+/// Per-sample iteration results of a whole render, stored in the same
+/// pixel order `Renderer::render_samples` uses, `samples` entries per pixel
+pub struct IterData {
+    pub width: i32,
+    pub height: i32,
+    pub samples: usize,
+    pub data: Vec<Sample>,
+}
+
+impl IterData {
+    pub fn new(width: i32, height: i32, samples: usize, data: Vec<Sample>) -> IterData {
+        IterData {
+            width,
+            height,
+            samples,
+            data,
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        for v in [
+            VERSION,
+            self.width as u32,
+            self.height as u32,
+            self.samples as u32,
+        ] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        for e in self.data.iter() {
+            e.write(&mut w)?;
+        }
+        w.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<IterData> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a fracgen iteration data file",
+            ));
+        }
+        if read_u32(&mut r)? != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported iteration data version",
+            ));
+        }
+        let width = read_u32(&mut r)? as i32;
+        let height = read_u32(&mut r)? as i32;
+        let samples = read_u32(&mut r)? as usize;
+        // the header is checked against the file before anything is allocated
+        let len = (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|n| n.checked_mul(samples as u64))
+            .filter(|n| n.checked_mul(SAMPLE_BYTES) == Some(size - HEADER))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "iteration data doesn't match its header",
+                )
+            })? as usize;
+        let mut data = Vec::with_capacity(len);
+        for _ in 0..len {
+            data.push(Sample::read(&mut r)?);
+        }
+        Ok(IterData::new(width, height, samples, data))
+    }
+}
+
+pub(crate) fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+pub(crate) fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(f64::from_le_bytes(b))
+}
+
+pub(crate) fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let data: Vec<Sample> = (0..2 * 3 * 2)
+            .map(|k| Sample {
+                i: k as f64,
+                s: k as f64 * 0.5,
+                z: Cf64::new(k as f64, -1.0),
+                der: Cf64::new(2.0, k as f64),
+                period: k as u32 % 3,
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("fracgen-iters-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        IterData::new(2, 3, 2, data.clone()).save(path).unwrap();
+        let loaded = IterData::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((loaded.width, loaded.height, loaded.samples), (2, 3, 2));
+        for (a, b) in data.iter().zip(loaded.data.iter()) {
+            assert_eq!(
+                (a.i, a.s, a.z, a.der, a.period),
+                (b.i, b.s, b.z, b.der, b.period)
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        let data = vec![
+            Sample {
+                i: 1.0,
+                s: 1.0,
+                z: Cf64::new(0.0, 0.0),
+                der: Cf64::new(1.0, 0.0),
+                period: 0,
+            };
+            4 * 4
+        ];
+        let path = std::env::temp_dir().join(format!("fracgen-cut-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        IterData::new(4, 4, 1, data).save(path).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::write(path, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(IterData::load(path).is_err());
+        // a header asking for far more samples than the file holds
+        let mut bytes = bytes;
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(path, &bytes).unwrap();
+        assert!(IterData::load(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,4 +1,41 @@
+mod attractor;
+mod checkpoint;
 mod color;
+mod density;
+mod distributed;
+mod explore;
+mod filter;
+mod flame;
+mod formula;
+mod hyper;
+mod iterdata;
+mod lyapunov;
+mod march;
+mod mariani;
+mod newton;
+mod progress;
+mod pyramid;
 mod renderer;
+mod sampler;
+mod scene;
+mod serve;
+mod simd;
+mod tiled;
+pub use attractor::Attractor;
 pub use color::{Color, ColorType};
+pub use density::Tone;
+pub use distributed::{coordinate, coordinate_on, work};
+pub use explore::explore;
+pub use filter::Filter;
+pub use flame::{Flame, Variation, Xform};
+pub use formula::{Formula, Hybrid, State, FORMULAS};
+pub use hyper::{Algebra, Bicomplex, Hypercomplex, Quaternion, Slice};
+pub use iterdata::{IterData, Sample};
+pub use march::{March, Shape};
+pub use pyramid::{render_pyramid, Layout};
 pub use renderer::{Args, Functions, Functs, Renderer};
+pub use sampler::Pattern;
+pub use scene::scene_argv;
+pub use serve::serve;
+pub use simd::{isa, Kernel};
+pub use tiled::{assemble_png, render_tile, render_tiles, TileGrid};
@@ -0,0 +1,127 @@
+// fracgen
+// Lyapunov fractals
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Markus-Lyapunov fractals: the logistic map x <- r x (1 - x) where r takes
+// turns being `a` and `b` as the `--lyapunov` sequence says, with (a, b) the
+// real and imaginary part of the pixel's point. The exponent
+//     λ = 1/N Σ ln |r (1 - 2x)|
+// is negative where the orbit settles down and positive where it's chaotic.
+
+use std::{fmt, str::FromStr};
+
+use num::complex::Complex;
+
+use crate::{iterdata::Sample, renderer::Renderer};
+
+type Cf64 = Complex<f64>;
+
+/// Steps run before the exponent is summed, so it doesn't depend on `x0`
+const WARMUP: usize = 200;
+/// Where every orbit starts
+const X0: f64 = 0.5;
+
+/// Which of `a` and `b` each step uses, `true` for `b`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequence(pub Vec<bool>);
+
+impl FromStr for Sequence {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let steps: Option<Vec<bool>> = string
+            .chars()
+            .map(|c| match c.to_ascii_uppercase() {
+                'A' => Some(false),
+                'B' => Some(true),
+                _ => None,
+            })
+            .collect();
+        match steps {
+            Some(steps) if !steps.is_empty() => Ok(Sequence(steps)),
+            _ => Err(format!(
+                "unknown sequence {}, expected only the letters A and B",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let letters: String = self.0.iter().map(|b| if *b { 'B' } else { 'A' }).collect();
+        write!(f, "{}", letters)
+    }
+}
+
+impl Renderer {
+    /// The Lyapunov exponent of the point `c` = (a, b) over `--limit` steps,
+    /// returned as `s`. Orbits that leave [0, 1] and never come back count as 0
+    pub fn lyapunov_sample(&self, c: Cf64, sequence: &Sequence) -> Sample {
+        let steps = &sequence.0;
+        let r = |n: usize| if steps[n % steps.len()] { c.im } else { c.re };
+        let mut x = X0;
+        for n in 0..WARMUP {
+            x = r(n) * x * (1.0 - x);
+        }
+        let count = self.args.limit.max(1.0) as usize;
+        let mut sum = 0.0;
+        for n in WARMUP..WARMUP + count {
+            let rn = r(n);
+            // x = 1/2 is a superstable point, its log would be -inf
+            sum += (rn * (1.0 - 2.0 * x)).abs().max(f64::MIN_POSITIVE).ln();
+            x = rn * x * (1.0 - x);
+        }
+        let lambda = sum / count as f64;
+        Sample {
+            i: 0.0,
+            s: if lambda.is_finite() { lambda } else { 0.0 },
+            z: Cf64::new(x, 0.0),
+            der: Cf64::new(1.0, 0.0),
+            period: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functs};
+
+    fn exponent(r: f64) -> f64 {
+        let mut args = Args::new();
+        args.lyapunov = Some("AB".parse().unwrap());
+        args.limit = 20000.0;
+        let rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        rend.lyapunov_sample(Cf64::new(r, r), args.lyapunov.as_ref().unwrap())
+            .s
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        let sequence: Sequence = "aabAB".parse().unwrap();
+        assert_eq!(sequence.0, [false, false, true, false, true]);
+        assert_eq!(sequence.to_string(), "AABAB");
+        assert!("ABC".parse::<Sequence>().is_err());
+        assert!("".parse::<Sequence>().is_err());
+    }
+
+    #[test]
+    fn exponents_tell_order_from_chaos() {
+        // a stable 2 cycle, and a chaotic orbit
+        assert!(exponent(3.2) < 0.0);
+        assert!(exponent(3.9) > 0.0);
+    }
+}
@@ -15,13 +15,188 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use clap::Parser;
-use fracgen::{Args, Functions, Functs, Renderer};
-use num::Complex;
-use std::{path, time::Instant};
-type Cf64 = Complex<f64>;
+use clap::{CommandFactory, ErrorKind, Parser};
+use fracgen::{
+    assemble_png, coordinate, explore, isa, render_pyramid, render_tiles, scene_argv, serve, work,
+    Args, Flame, Formula, Functs, IterData, Renderer, TileGrid,
+};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame,
+};
+use std::{env, fs, path, time::Instant};
+
+fn save_heatmap(rend: &Renderer, name: &str) {
+    rend.sample_heatmap(rend.args.max_samples)
+        .save(name.replace(".png", "_samples.png"))
+        .unwrap();
+}
+
+/// `--depth` and `--normals` of a `--march` render, beside the image
+fn save_buffers(rend: &Renderer, name: &str) {
+    let march = match &rend.functs.march {
+        Some(march) if rend.args.depth || rend.args.normals => march,
+        _ => return,
+    };
+    let (depth, normals) = rend.march_buffers(march);
+    if rend.args.depth {
+        depth.save(name.replace(".png", "_depth.png")).unwrap();
+    }
+    if rend.args.normals {
+        normals.save(name.replace(".png", "_normals.png")).unwrap();
+    }
+}
+
+fn add_samples(rend: &mut Renderer, path: &str) {
+    let args = rend.args.clone();
+    if path::Path::new(path).exists() {
+        let (pass, done) = rend.load_checkpoint(path).unwrap();
+        if pass > 0 {
+            rend.resume_samples(pass, done, true);
+        }
+        println!("Loaded {} samples from {}", rend.rendered_samples, path);
+    }
+    rend.render_samples(args.add_samples.unwrap_or(args.samples), true);
+    if args.noise > 0.0 {
+        rend.render_adaptive(args.adaptive_step, args.noise, args.max_samples);
+    }
+    rend.save_checkpoint(path, 0, 0).unwrap();
+    println!("Saved {} samples to {}", rend.rendered_samples, path);
+}
+
+fn cycle_palette(rend: &mut Renderer, path: &str) {
+    let data = IterData::load(path).unwrap();
+    // the frame is the one the data was saved from, whatever the flags say
+    if (data.width, data.height) != (rend.width, rend.height) {
+        rend.resize(data.width as usize, data.height as usize);
+    }
+    let args = rend.args.clone();
+    let mut gif = None;
+    let dir = format!("out{}{}_cycle", path::MAIN_SEPARATOR, args.name);
+    if args.gif {
+        let file = fs::File::create(format!("{}.gif", dir)).unwrap();
+        let mut encoder = GifEncoder::new_with_speed(file, 10);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        gif = Some(encoder);
+    } else {
+        fs::create_dir_all(&dir).unwrap();
+    }
+    for frame in 0..args.frames {
+        let now = Instant::now();
+        let mut frame_args = args.clone();
+        frame_args.phase = args.phase + 360.0 * (frame as f64 / args.frames as f64);
+        rend.update_args(frame_args);
+        rend.recolor(&data);
+        rend.process_image();
+        match gif.as_mut() {
+            Some(encoder) => encoder
+                .encode_frame(Frame::from_parts(
+                    rend.image.clone(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(1000, 30),
+                ))
+                .unwrap(),
+            None => rend
+                .image
+                .save(format!("{}{}{:04}.png", dir, path::MAIN_SEPARATOR, frame))
+                .unwrap(),
+        }
+        println!(
+            "frame {}/{} in {}ms",
+            frame + 1,
+            args.frames,
+            now.elapsed().as_millis()
+        );
+    }
+}
+
+/// Parses the command line, settings from `--scene` come first so flags
+/// given next to it override them
+fn parse_args(argv: &[String]) -> Args {
+    let args = Args::parse_from(argv);
+    match &args.scene {
+        Some(path) => {
+            let scene = fs::read_to_string(path).unwrap();
+            Args::parse_from(
+                argv[..1]
+                    .iter()
+                    .cloned()
+                    .chain(scene_argv(&scene))
+                    .chain(argv[1..].iter().cloned()),
+            )
+        }
+        None => args,
+    }
+}
+
+/// Refuses flags that don't work together with clap's usage error
+fn check_conflicts(args: &Args, command: Option<&str>) {
+    // density renders need the whole frame at once
+    let split = args.tile > 0
+        || args.listen.is_some()
+        || args.pyramid.is_some()
+        || command == Some("serve");
+    let conflicts = [
+        (
+            args.listen.is_some() && args.send_iters && args.noise > 0.0,
+            "--send-iters sends fixed sample counts, it can't be used with --noise",
+        ),
+        (
+            args.buddha && split,
+            "--buddha renders the whole frame at once, it can't be used with --tile, --listen, --pyramid or serve",
+        ),
+        (
+            args.attractor.is_some() && split,
+            "--attractor renders the whole frame at once, it can't be used with --tile, --listen, --pyramid or serve",
+        ),
+        (
+            args.hyper.is_some()
+                && (args.hybrid.is_some() || args.fractal_mode != Formula::Mandelbrot),
+            "--hyper only iterates z² + c, it can't be used with --hybrid or another --fractal-mode",
+        ),
+        (
+            args.flame.is_some() && split,
+            "--flame renders the whole frame at once, it can't be used with --tile, --listen, --pyramid or serve",
+        ),
+        (
+            (args.depth || args.normals) && split,
+            "--depth and --normals are saved for the whole frame, they can't be used with --tile, --listen, --pyramid or serve",
+        ),
+        (
+            args.march.is_some() && args.send_iters,
+            "--send-iters sends iteration counts, --march renders have none",
+        ),
+        (
+            args.march.is_some() && (args.save_iters.is_some() || args.cycle.is_some()),
+            "--save-iters and --cycle keep iteration counts, --march renders have none",
+        ),
+    ];
+    if let Some((_, msg)) = conflicts.iter().find(|(conflict, _)| *conflict) {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, msg)
+            .exit();
+    }
+}
+
 fn main() {
-    let args = Args::parse();
+    let mut argv: Vec<String> = env::args().collect();
+    let command = match argv.get(1).map(String::as_str) {
+        Some("explore") | Some("serve") => Some(argv.remove(1)),
+        _ => None,
+    };
+    let mut args = parse_args(&argv);
+    check_conflicts(&args, command.as_deref());
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .unwrap();
+    if command.as_deref() == Some("explore") {
+        match explore(args).unwrap() {
+            Some(view) => args = view,
+            None => return,
+        }
+    }
     let name = format!(
         "out{}{}_{}x{}-{}_s{}-{}-f{}-c{}-d{}.png",
         path::MAIN_SEPARATOR,
@@ -35,77 +210,122 @@ fn main() {
         args.color_mode,
         args.bail_mode,
     );
+    if command.as_deref() == Some("serve") {
+        let addr = args.listen.as_deref().unwrap_or("127.0.0.1:8080");
+        serve(&args, addr).unwrap();
+        return;
+    }
+    if let Some(addr) = &args.connect {
+        println!("Working for {} with {} threads...", addr, args.threads);
+        work(addr.as_str()).unwrap();
+        return;
+    }
     println!("Now processing {} with {} threads...", name, args.threads);
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(args.threads)
-        .build_global()
-        .unwrap();
     let now = Instant::now();
-    let mut mandelbrot = Renderer::new(
-        args.clone(),
-        Functs::new(
-            match args.fractal_mode {
-                0 => match args.is_julia {
-                    true => |z, _, j| z * z + j,
-                    false => |z, c, _| z * z + c,
-                },
-                1 => match args.is_julia {
-                    true => |z, _, j| {
-                        Cf64::new(z.re.abs(), z.im.abs()) * Cf64::new(z.re.abs(), z.im.abs()) + j
-                    },
-                    false => |z, c, _| {
-                        Cf64::new(z.re.abs(), z.im.abs()) * Cf64::new(z.re.abs(), z.im.abs()) + c
-                    },
-                },
-                2 => match args.is_julia {
-                    true => |z, _, j| {
-                        let mut tz = z * z + j;
-                        tz = Cf64::new(tz.re.abs(), tz.im.abs())
-                            * Cf64::new(tz.re.abs(), tz.im.abs())
-                            + j;
-                        tz = tz * tz + j;
-                        tz = tz * tz + j;
-                        tz
-                    },
-                    false => |z, c, _| {
-                        let mut tz = z * z + c;
-                        tz = Cf64::new(tz.re.abs(), tz.im.abs())
-                            * Cf64::new(tz.re.abs(), tz.im.abs())
-                            + c;
-                        tz = tz * tz + c;
-                        tz = tz * tz + c;
-                        tz
-                    },
-                },
-                3 => match args.is_julia {
-                    true => |z, _, j| (z * j).powc(z / j) + (z / j),
-                    false => |z, c, _| (z * c).powc(z / c) + (z / c),
-                },
-                _ => match args.is_julia {
-                    true => |z, _, j| z * z + j,
-                    false => |z, c, _| z * z + c,
-                },
+    let functs = Functs::from_args(&args);
+    if args.check_kernel {
+        let rend = Renderer::new(args.clone(), functs);
+        match rend.functs.kernel {
+            Some(kernel) => println!(
+                "{:?} kernel on {}: {} samples differ from the scalar path",
+                kernel.formula,
+                isa(),
+                rend.check_kernel()
+            ),
+            None => println!("no SIMD kernel for this formula, the scalar path is used"),
+        }
+        return;
+    }
+    if let Some(addr) = &args.listen {
+        let mut mandelbrot = coordinate(&args, &functs, addr.as_str()).unwrap();
+        mandelbrot.process_image();
+        mandelbrot.image.save(&name).unwrap();
+        if args.heatmap {
+            save_heatmap(&mandelbrot, &name);
+        }
+        println!("Finished in: {}ms!", now.elapsed().as_millis());
+        return;
+    }
+    if let Some(layout) = args.pyramid {
+        let dir = format!("out{}{}_{}", path::MAIN_SEPARATOR, args.name, layout);
+        render_pyramid(&args, &functs, layout, &dir).unwrap();
+        println!("Finished in: {}ms!", now.elapsed().as_millis());
+        return;
+    }
+    if args.tile > 0 {
+        let grid = TileGrid::new(args.width, args.height, args.tile);
+        let dir = format!("out{}{}_tiles", path::MAIN_SEPARATOR, args.name);
+        render_tiles(&args, &functs, &grid, &dir).unwrap();
+        if !args.keep_tiles {
+            assemble_png(&grid, &dir, &name).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        println!("Finished in: {}ms!", now.elapsed().as_millis());
+        return;
+    }
+    let mut mandelbrot = Renderer::new(args.clone(), functs);
+    if let Some(path) = &args.flame {
+        let flame = Flame::load(path).unwrap();
+        mandelbrot.render_flame(&flame, true);
+        mandelbrot.image.save(&name).unwrap();
+    } else if args.attractor.is_some() {
+        mandelbrot.render_attractor(true).unwrap();
+        mandelbrot.image.save(&name).unwrap();
+    } else if args.buddha {
+        mandelbrot.render_density(true).unwrap();
+        mandelbrot.image.save(&name).unwrap();
+    } else if let Some(path) = &args.cycle {
+        cycle_palette(&mut mandelbrot, path);
+    } else if !args.merge.is_empty() {
+        for path in args.merge.iter() {
+            mandelbrot.merge_accum(path).unwrap();
+        }
+        if let Some(path) = &args.accum {
+            mandelbrot.save_checkpoint(path, 0, 0).unwrap();
+        }
+        mandelbrot.process_image();
+        mandelbrot.image.save(&name).unwrap();
+    } else if let Some(path) = &args.accum {
+        add_samples(&mut mandelbrot, path);
+        mandelbrot.process_image();
+        mandelbrot.image.save(&name).unwrap();
+        if args.heatmap {
+            save_heatmap(&mandelbrot, &name);
+        }
+        save_buffers(&mandelbrot, &name);
+    } else {
+        match &args.save_iters {
+            Some(path) => mandelbrot
+                .record_samples(args.samples, true)
+                .save(path)
+                .unwrap(),
+            None => match &args.checkpoint {
+                Some(path) if args.resume && path::Path::new(path).exists() => {
+                    let (pass, done) = mandelbrot.load_checkpoint(path).unwrap();
+                    println!(
+                        "Resuming from {} ({} samples done, {}/{} pixels of the current pass)",
+                        path,
+                        mandelbrot.rendered_samples,
+                        done,
+                        args.width * args.height
+                    );
+                    if pass > 0 {
+                        mandelbrot.resume_samples(pass, done, true);
+                    }
+                }
+                _ => mandelbrot.render_samples(args.samples, true),
             },
-            move |z, _| z,
-            Functions::identity,
-            match args.color_mode {
-                0 => Functions::coloring,
-                1 => Functions::normal_map,
-                2 => Functions::miles_coloring,
-                3 => Functions::miles_coloring2,
-                _ => Functions::coloring,
-            },
-            match args.bail_mode {
-                0 => Functions::default_bail,
-                1 => Functions::sqrt_bail,
-                2 => Functions::der_bail,
-                _ => Functions::default_bail,
-            },
-        ),
-    );
-    mandelbrot.render_samples(args.samples, true);
-    mandelbrot.process_image();
-    mandelbrot.image.save(&name).unwrap();
+        }
+        if args.noise > 0.0 {
+            mandelbrot.render_adaptive(args.adaptive_step, args.noise, args.max_samples);
+        }
+        mandelbrot.process_image();
+        mandelbrot.image.save(&name).unwrap();
+        if args.heatmap {
+            save_heatmap(&mandelbrot, &name);
+        }
+        save_buffers(&mandelbrot, &name);
+    }
     // output.save(&name).unwrap();
     let notif = format!("Finished in: {}ms!", now.elapsed().as_millis());
     println!(
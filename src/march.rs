@@ -0,0 +1,531 @@
+// fracgen
+// Ray marched 3D fractals
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// `--march` renders a 3D fractal by sphere tracing its distance estimate:
+// every sample is a ray from `--camera` towards `--look-at`, spread over
+// `--fov` degrees across the width, that steps forward by the estimated
+// distance to the surface until it's within a pixel's width of it. Hits are
+// lit by a directional `--light` with soft shadows (`--shadow` is how sharp
+// they are, 0 turns them off) and ambient occlusion (`--ao`), then faded into
+// the background by `--fog`. `--glow` brightens rays that passed close to the
+// surface. Each sample is one ray, so supersampling, filters, tiles and the
+// thread pool all work as in 2D.
+//
+// `--params` are the shape's: the mandelbulb's power (8), the mandelbox's
+// scale, minimum and fixed radius (2, 0.5, 1), the menger sponge's scale (3)
+// and the quaternion julia's c (-0.2, 0.6, 0.2, 0), sliced at w = 0.
+
+use std::{fmt, str::FromStr};
+
+use image::{ImageBuffer, Luma, Rgba};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    color::{Color, ColorType},
+    hyper::{Algebra, Quaternion},
+    renderer::{Args, Renderer},
+};
+
+type V3 = [f64; 3];
+type Img8 = ImageBuffer<Rgba<u8>, Vec<u8>>;
+type Img16 = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// Steps a ray takes before it's given up on
+const MAX_STEPS: usize = 256;
+/// Rays that get this far from the camera missed
+const FAR: f64 = 64.0;
+/// Steps of the shadow rays
+const SHADOW_STEPS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Mandelbulb,
+    Mandelbox,
+    Menger,
+    QuatJulia,
+}
+
+impl FromStr for Shape {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "mandelbulb" => Ok(Shape::Mandelbulb),
+            "mandelbox" => Ok(Shape::Mandelbox),
+            "menger" => Ok(Shape::Menger),
+            "quatjulia" => Ok(Shape::QuatJulia),
+            _ => Err(format!(
+                "unknown shape {}, expected mandelbulb, mandelbox, menger or quatjulia",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Shape::Mandelbulb => "mandelbulb",
+            Shape::Mandelbox => "mandelbox",
+            Shape::Menger => "menger",
+            Shape::QuatJulia => "quatjulia",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn add(a: V3, b: V3) -> V3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: V3, b: V3) -> V3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: V3, s: f64) -> V3 {
+    a.map(|v| v * s)
+}
+
+fn dot(a: V3, b: V3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: V3, b: V3) -> V3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: V3) -> V3 {
+    scale(a, 1.0 / dot(a, a).sqrt())
+}
+
+/// The first 3 of `v`, or `default` where it's short
+fn vec3(v: &[f64], default: V3) -> V3 {
+    std::array::from_fn(|k| v.get(k).copied().unwrap_or(default[k]))
+}
+
+impl Shape {
+    /// Parameters used when `--params` isn't given
+    pub fn default_params(&self) -> Vec<f64> {
+        match self {
+            Shape::Mandelbulb => vec![8.0],
+            Shape::Mandelbox => vec![2.0, 0.5, 1.0],
+            Shape::Menger => vec![3.0],
+            Shape::QuatJulia => vec![-0.2, 0.6, 0.2, 0.0],
+        }
+    }
+
+    /// Iterations of the distance estimate when `--de-iters` isn't given
+    pub fn iterations(&self) -> usize {
+        match self {
+            Shape::Mandelbulb => 12,
+            Shape::Mandelbox => 15,
+            Shape::Menger => 5,
+            Shape::QuatJulia => 12,
+        }
+    }
+
+    /// A lower bound of the distance from `p` to the surface, and an orbit
+    /// trap in [0, 1] for coloring
+    pub fn distance(&self, p: V3, k: &[f64], iters: usize) -> (f64, f64) {
+        match self {
+            Shape::Mandelbulb => {
+                let n = k[0];
+                let (mut z, mut dr, mut r) = (p, 1.0, 0.0);
+                let mut trap = f64::INFINITY;
+                for _ in 0..iters {
+                    r = dot(z, z).sqrt();
+                    if r > 2.0 {
+                        break;
+                    }
+                    // the origin has no direction, it maps straight to p
+                    let theta = if r > 0.0 { (z[2] / r).acos() * n } else { 0.0 };
+                    let phi = z[1].atan2(z[0]) * n;
+                    dr = r.powf(n - 1.0) * n * dr + 1.0;
+                    let (st, ct) = theta.sin_cos();
+                    let (sp, cp) = phi.sin_cos();
+                    z = add(scale([st * cp, st * sp, ct], r.powf(n)), p);
+                    trap = trap.min(dot(z, z));
+                }
+                let d = if r > 0.0 { 0.5 * r.ln() * r / dr } else { 0.0 };
+                (d, trap.sqrt().min(1.0))
+            }
+            Shape::Mandelbox => {
+                let (s, min2, fixed2) = (k[0], k[1] * k[1], k[2] * k[2]);
+                let (mut z, mut dr) = (p, 1.0);
+                let mut trap = f64::INFINITY;
+                for _ in 0..iters {
+                    // folds the box, then the sphere
+                    z = z.map(|v| v.clamp(-1.0, 1.0) * 2.0 - v);
+                    let r2 = dot(z, z);
+                    let t = if r2 < min2 {
+                        fixed2 / min2
+                    } else if r2 < fixed2 {
+                        fixed2 / r2
+                    } else {
+                        1.0
+                    };
+                    z = add(scale(z, t * s), p);
+                    dr = dr * t * s.abs() + 1.0;
+                    trap = trap.min(r2);
+                }
+                (dot(z, z).sqrt() / dr.abs(), (trap / fixed2).sqrt().min(1.0))
+            }
+            Shape::Menger => {
+                let s = k[0];
+                let q = p.map(f64::abs);
+                // the unit cube the holes are cut from
+                let mut d = (q[0] - 1.0).max(q[1] - 1.0).max(q[2] - 1.0);
+                let mut size = 1.0;
+                let mut trap = 1.0f64;
+                for _ in 0..iters {
+                    let a = p.map(|v| (v * size).rem_euclid(2.0) - 1.0);
+                    size *= s;
+                    let r = a.map(|v| (1.0 - s * v.abs()).abs());
+                    let da = r[0].max(r[1]);
+                    let db = r[1].max(r[2]);
+                    let dc = r[2].max(r[0]);
+                    let c = (da.min(db).min(dc) - 1.0) / size;
+                    if c > d {
+                        d = c;
+                        trap = trap.min(da.min(db).min(dc) / s);
+                    }
+                }
+                (d, trap.clamp(0.0, 1.0))
+            }
+            Shape::QuatJulia => {
+                let c = Quaternion([k[0], k[1], k[2], k[3]]);
+                let mut q = Quaternion([p[0], p[1], p[2], 0.0]);
+                // |q'| only, the derivative's length grows by 2|q| a step
+                let mut dq = 1.0;
+                let mut trap = f64::INFINITY;
+                for _ in 0..iters {
+                    let r2 = q.norm_sqr();
+                    if r2 > 16.0 {
+                        break;
+                    }
+                    dq *= 2.0 * r2.sqrt();
+                    q = q * q + c;
+                    trap = trap.min(q.norm_sqr());
+                }
+                let r = q.norm_sqr().sqrt();
+                (0.5 * r * r.ln() / dq, trap.sqrt().min(1.0))
+            }
+        }
+    }
+}
+
+/// Everything a ray needs that doesn't change from one to the next
+#[derive(Clone, Debug, PartialEq)]
+pub struct March {
+    pub shape: Shape,
+    pub params: Vec<f64>,
+    pub iterations: usize,
+    pub camera: V3,
+    /// where the camera looks, its right and its up
+    pub forward: V3,
+    pub right: V3,
+    pub up: V3,
+    /// half the width of the view at distance 1
+    pub spread: f64,
+    /// direction towards the light
+    pub light: V3,
+    pub shadow: f64,
+    pub ao: f64,
+    pub fog: f64,
+    pub glow: f64,
+}
+
+/// Where a ray ended up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// distance from the camera, `None` for a miss
+    pub depth: Option<f64>,
+    pub normal: V3,
+    pub trap: f64,
+    /// the closest the ray came to the surface relative to how far it went
+    pub closest: f64,
+}
+
+impl March {
+    pub fn from_args(args: &Args) -> Option<March> {
+        let shape = args.march?;
+        let params = match args.params.len() {
+            0 => shape.default_params(),
+            _ => args.params.clone(),
+        };
+        let params = (0..shape.default_params().len())
+            .map(|k| params.get(k).copied().unwrap_or(shape.default_params()[k]))
+            .collect();
+        let camera = vec3(&args.camera, [0.0, 0.0, -3.0]);
+        let forward = normalize(sub(vec3(&args.look_at, [0.0; 3]), camera));
+        // straight up or down, any right will do
+        let right = match cross([0.0, 1.0, 0.0], forward) {
+            r if dot(r, r) < 1e-12 => [1.0, 0.0, 0.0],
+            r => normalize(r),
+        };
+        Some(March {
+            shape,
+            params,
+            iterations: args.de_iters.unwrap_or_else(|| shape.iterations()),
+            camera,
+            forward,
+            right,
+            up: cross(forward, right),
+            spread: (args.fov.to_radians() / 2.0).tan(),
+            light: normalize(vec3(&args.light, [-0.5, 0.8, -0.6])),
+            shadow: args.shadow,
+            ao: args.ao,
+            fog: args.fog,
+            glow: args.glow,
+        })
+    }
+
+    fn distance(&self, p: V3) -> (f64, f64) {
+        self.shape.distance(p, &self.params, self.iterations)
+    }
+
+    /// The ray through `(sx, sy)`, -1 to 1 across the width with y down
+    pub fn ray(&self, sx: f64, sy: f64) -> V3 {
+        normalize(add(
+            self.forward,
+            add(
+                scale(self.right, sx * self.spread),
+                scale(self.up, -sy * self.spread),
+            ),
+        ))
+    }
+
+    /// Sphere traces `dir` from the camera, `pixel` is the angle a pixel
+    /// spans so hits are accurate to the pixel they're in
+    pub fn trace(&self, dir: V3, pixel: f64) -> Hit {
+        let mut t = 0.0;
+        let mut closest = f64::INFINITY;
+        for _ in 0..MAX_STEPS {
+            let p = add(self.camera, scale(dir, t));
+            let (d, trap) = self.distance(p);
+            closest = closest.min(d / t.max(1e-3));
+            if d < (pixel * t).max(1e-6) {
+                return Hit {
+                    depth: Some(t),
+                    normal: self.normal(p, pixel * t),
+                    trap,
+                    closest: 0.0,
+                };
+            }
+            t += d;
+            if t > FAR || !t.is_finite() {
+                break;
+            }
+        }
+        Hit {
+            depth: None,
+            normal: [0.0; 3],
+            trap: 0.0,
+            closest,
+        }
+    }
+
+    /// Gradient of the distance estimate at `p`, from the four corners of a
+    /// tetrahedron `e` across
+    fn normal(&self, p: V3, e: f64) -> V3 {
+        let e = e.max(1e-6);
+        let n = [
+            [1.0, -1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [-1.0, 1.0, -1.0],
+            [1.0, 1.0, 1.0],
+        ]
+        .iter()
+        .fold([0.0; 3], |n, k| {
+            add(n, scale(*k, self.distance(add(p, scale(*k, e))).0))
+        });
+        normalize(n)
+    }
+
+    /// How much of the light reaches `p`, with a penumbra where a shadow ray
+    /// only just missed something
+    fn soft_shadow(&self, p: V3) -> f64 {
+        if self.shadow <= 0.0 {
+            return 1.0;
+        }
+        let mut lit = 1.0f64;
+        let mut t = 0.01;
+        for _ in 0..SHADOW_STEPS {
+            let d = self.distance(add(p, scale(self.light, t))).0;
+            if d < 1e-4 {
+                return 0.0;
+            }
+            lit = lit.min(self.shadow * d / t);
+            t += d.clamp(0.01, 0.5);
+            if t > FAR / 2.0 {
+                break;
+            }
+        }
+        lit.clamp(0.0, 1.0)
+    }
+
+    /// 1 in the open, less in creases where the surface is close all around
+    fn occlusion(&self, p: V3, n: V3) -> f64 {
+        let mut occluded = 0.0;
+        let mut weight = 1.0;
+        for k in 1..=5 {
+            let h = 0.02 + 0.06 * k as f64;
+            occluded += (h - self.distance(add(p, scale(n, h))).0) * weight;
+            weight *= 0.7;
+        }
+        (1.0 - self.ao * 3.0 * occluded).clamp(0.0, 1.0)
+    }
+
+    /// Lit color of the ray `dir`, linear rgb
+    pub fn shade(&self, dir: V3, hit: &Hit, phase: f64) -> V3 {
+        // a dim gradient behind everything
+        let sky = 0.5 + 0.5 * -dir[1];
+        let background = [0.04 + 0.04 * sky, 0.05 + 0.05 * sky, 0.08 + 0.1 * sky];
+        let glow = self.glow * (-hit.closest * 40.0).exp();
+        let t = match hit.depth {
+            Some(t) => t,
+            None => return add(background, [glow * 0.6, glow * 0.8, glow]),
+        };
+        let p = add(self.camera, scale(dir, t));
+        let n = hit.normal;
+        // off the surface a little, so the shadow ray doesn't hit where it starts
+        let lifted = add(p, scale(n, 1e-3));
+        let diffuse = dot(n, self.light).max(0.0) * self.soft_shadow(lifted);
+        let half = normalize(sub(self.light, dir));
+        let specular = dot(n, half).max(0.0).powi(32) * diffuse;
+        let ambient = 0.25 * self.occlusion(p, n) * (0.6 + 0.4 * n[1].max(0.0));
+        let albedo = Color::from_hsv((hit.trap * 360.0 + phase).rem_euclid(360.0), 0.55, 0.9, 1.0);
+        let lit: V3 = std::array::from_fn(|k| albedo.ch[k] * (diffuse + ambient) + specular * 0.3);
+        let fade = (-self.fog * t * t).exp();
+        std::array::from_fn(|k| lit[k] * fade + background[k] * (1.0 - fade))
+    }
+}
+
+impl Renderer {
+    /// Screen position of the sample at `offset` of frame pixel (`x`, `y`)
+    fn screen(&self, x: i32, y: i32, offset: (f64, f64)) -> (f64, f64) {
+        let (w, h) = (self.args.width as f64, self.args.height as f64);
+        let px = x as f64 + (offset.0 * 2.0 - 1.0) / self.args.sampled;
+        let py = y as f64 + (offset.1 * 2.0 - 1.0) / self.args.sampled;
+        (2.0 * (px / w) - 1.0, (2.0 * (py / h) - 1.0) * (h / w))
+    }
+
+    /// The contribution of one ray, like `shade` gives for a 2D sample
+    pub fn march_sample(&self, march: &March, x: i32, y: i32, offset: (f64, f64)) -> Color {
+        let (sx, sy) = self.screen(x, y, offset);
+        let dir = march.ray(sx, sy);
+        let hit = march.trace(dir, 2.0 * march.spread / self.args.width as f64);
+        let rgb = march.shade(dir, &hit, self.args.phase);
+        let color = Color::new(
+            [
+                rgb[0].clamp(0.0, 1.0),
+                rgb[1].clamp(0.0, 1.0),
+                rgb[2].clamp(0.0, 1.0),
+                1.0,
+            ],
+            ColorType::Rgba,
+        )
+        .to_sRgba();
+        color * color
+    }
+
+    /// Depth and normals of the ray through the middle of every pixel. Depth
+    /// is 16 bit, black at the camera and white at the far plane and for
+    /// misses, normals are mapped from [-1, 1] to [0, 255] with misses
+    /// left transparent
+    pub fn march_buffers(&self, march: &March) -> (Img16, Img8) {
+        let (w, h) = (self.width, self.height);
+        let hits: Vec<Hit> = (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % w + self.offset.0, i / w + self.offset.1);
+                let (sx, sy) = self.screen(x, y, (0.5, 0.5));
+                march.trace(
+                    march.ray(sx, sy),
+                    2.0 * march.spread / self.args.width as f64,
+                )
+            })
+            .collect();
+        let depth = ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+            let t = hits[(y * w as u32 + x) as usize].depth.unwrap_or(FAR);
+            Luma([((t / FAR).min(1.0) * u16::MAX as f64) as u16])
+        });
+        let normals = ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+            let hit = &hits[(y * w as u32 + x) as usize];
+            match hit.depth {
+                Some(_) => {
+                    let n = hit.normal.map(|v| ((v * 0.5 + 0.5) * 255.0).round() as u8);
+                    Rgba([n[0], n[1], n[2], 255])
+                }
+                None => Rgba([0, 0, 0, 0]),
+            }
+        });
+        (depth, normals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: [Shape; 4] = [
+        Shape::Mandelbulb,
+        Shape::Mandelbox,
+        Shape::Menger,
+        Shape::QuatJulia,
+    ];
+
+    fn estimate(shape: Shape, p: V3) -> f64 {
+        shape
+            .distance(p, &shape.default_params(), shape.iterations())
+            .0
+    }
+
+    #[test]
+    fn far_points_are_outside() {
+        for shape in SHAPES {
+            for p in [[0.0, 0.0, 10.0], [-20.0, 3.0, 0.5], [12.0, -12.0, -12.0]] {
+                let d = estimate(shape, p);
+                assert!(d > 0.0, "{:?} at {:?}: {}", shape, p, d);
+            }
+        }
+    }
+
+    #[test]
+    fn inside_points_are_not() {
+        for (shape, p) in [
+            (Shape::Mandelbulb, [0.0, 0.0, 0.0]),
+            (Shape::Mandelbulb, [0.1, -0.1, 0.05]),
+            (Shape::Mandelbox, [0.0, 0.0, 0.0]),
+            (Shape::Menger, [0.98, 0.98, 0.98]),
+            (Shape::QuatJulia, [-0.2, 0.6, 0.2]),
+        ] {
+            let d = estimate(shape, p);
+            assert!(d <= 0.0, "{:?} at {:?}: {}", shape, p, d);
+        }
+    }
+
+    #[test]
+    fn the_sponge_is_hollow() {
+        // the middle third is cut out on every axis
+        let d = estimate(Shape::Menger, [0.0, 0.0, 0.0]);
+        assert!((d - 1.0 / 3.0).abs() < 1e-12, "{}", d);
+    }
+}
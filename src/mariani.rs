@@ -0,0 +1,225 @@
+// fracgen
+// Interior filling by rectangle subdivision
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Mariani-Silver: a rectangle whose whole border is inside the set has only
+// the set inside it (the set and its complement are both connected), so its
+// pixels don't need to be iterated. The border also has to share one period,
+// so the fill can take it over for `--period-color`. A rectangle with a mixed border is split
+// in two along its longer side until it's too small to be worth it.
+//
+// That only holds for connected sets and for the continuous border, not the
+// pixel centers we actually look at, so before a rectangle is filled a grid
+// of points inside it is iterated as well and any escaping one splits it.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use num::Complex;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{formula::Formula, renderer::Renderer};
+
+/// x, y, w, h in this renderer's pixels
+type Rect = (i32, i32, i32, i32);
+
+/// Rectangles smaller than this are always iterated pixel by pixel
+const MIN_SIZE: i32 = 8;
+/// Probe points per side checked inside a rectangle before it's filled
+const PROBES: i32 = 4;
+
+// cache cells hold the period of pixels in the set, or one of these
+const UNKNOWN: u32 = u32::MAX;
+const OUTSIDE: u32 = u32::MAX - 1;
+
+/// Pixel centers that were iterated already, borders of neighbouring
+/// rectangles overlap
+struct Cache {
+    width: i32,
+    cells: Vec<AtomicU32>,
+}
+
+impl Renderer {
+    /// With `--mariani`, marks the pixels of this renderer that lie in
+    /// rectangles bounded by the set, `sample` skips iterating those.
+    /// Only runs once, the mask depends on the view and not on the samples
+    pub fn trace_interior(&mut self) {
+        if !self.args.mariani || !self.interior.is_empty() || !self.connected() {
+            return;
+        }
+        let cache = Cache {
+            width: self.width,
+            cells: (0..self.width * self.height)
+                .map(|_| AtomicU32::new(UNKNOWN))
+                .collect(),
+        };
+        let rects = self.subdivide(&cache, (0, 0, self.width, self.height));
+        // a filled pixel's samples must not reach past the traced border
+        let margin = (1.0 / self.args.sampled).ceil().max(1.0) as i32;
+        let mut interior = vec![vec![None; self.width as usize]; self.height as usize];
+        for ((x, y, w, h), period) in rects {
+            for row in interior[(y + margin) as usize..(y + h - margin).max(y + margin) as usize]
+                .iter_mut()
+            {
+                for e in
+                    row[(x + margin) as usize..(x + w - margin).max(x + margin) as usize].iter_mut()
+                {
+                    *e = Some(period);
+                }
+            }
+        }
+        self.interior = interior;
+    }
+
+    /// The period frame pixel (`x`, `y`) was filled with by `trace_interior`
+    pub fn filled_period(&self, x: i32, y: i32) -> Option<u32> {
+        let (x, y) = (x - self.offset.0, y - self.offset.1);
+        if x < 0 || y < 0 {
+            return None;
+        }
+        *self.interior.get(y as usize)?.get(x as usize)?
+    }
+
+    /// Whether the set is one the fill holds for: the mandelbrot set and
+    /// julia sets of z² + c. Other formulas, hybrids and the other modes can
+    /// have islands inside a border that's all in the set
+    fn connected(&self) -> bool {
+        self.functs.formulas == [Formula::Mandelbrot]
+            && (self.args.is_julia || self.args.z_init == Complex::new(0.0, 0.0))
+            && self.args.newton.is_empty()
+            && self.args.lyapunov.is_none()
+            && self.functs.slice.is_none()
+            && self.functs.march.is_none()
+    }
+
+    /// Period of the pixel center, or `OUTSIDE`
+    fn period(&self, cache: &Cache, x: i32, y: i32) -> u32 {
+        let cell = &cache.cells[(y * cache.width + x) as usize];
+        match cell.load(Ordering::Relaxed) {
+            UNKNOWN => {
+                let e = self.sample(
+                    x + self.offset.0,
+                    y + self.offset.1,
+                    self.pixel_delta(),
+                    (0.5, 0.5),
+                );
+                let period = if e.i >= self.args.limit {
+                    e.period
+                } else {
+                    OUTSIDE
+                };
+                cell.store(period, Ordering::Relaxed);
+                period
+            }
+            period => period,
+        }
+    }
+
+    fn subdivide(&self, cache: &Cache, rect: Rect) -> Vec<(Rect, u32)> {
+        let (x, y, w, h) = rect;
+        if w < MIN_SIZE || h < MIN_SIZE {
+            return Vec::new();
+        }
+        let mut border: Vec<(i32, i32)> = Vec::with_capacity(2 * (w + h) as usize);
+        for px in x..x + w {
+            border.push((px, y));
+            border.push((px, y + h - 1));
+        }
+        for py in y + 1..y + h - 1 {
+            border.push((x, py));
+            border.push((x + w - 1, py));
+        }
+        let period = self.period(cache, x, y);
+        let same = |&(px, py): &(i32, i32)| self.period(cache, px, py) == period;
+        if period != OUTSIDE && border.par_iter().all(same) {
+            let probes: Vec<(i32, i32)> = (1..PROBES)
+                .flat_map(|j| (1..PROBES).map(move |k| (x + w * j / PROBES, y + h * k / PROBES)))
+                .collect();
+            if probes.par_iter().all(same) {
+                return vec![(rect, period)];
+            }
+        }
+        // halves share the splitting line so no pixel falls between borders
+        let (a, b) = if w >= h {
+            let half = w / 2;
+            ((x, y, half + 1, h), (x + half, y, w - half, h))
+        } else {
+            let half = h / 2;
+            ((x, y, w, half + 1), (x, y + half, w, h - half))
+        };
+        let (mut a, b) = rayon::join(|| self.subdivide(cache, a), || self.subdivide(cache, b));
+        a.extend(b);
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functions, Functs};
+
+    fn traced(args: Args) -> Renderer {
+        let args = Args {
+            width: 96,
+            height: 64,
+            limit: 256.0,
+            mariani: true,
+            ..args
+        };
+        let mut rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        rend.trace_interior();
+        rend
+    }
+
+    /// Iterates every pixel the trace filled without the fill or the bulb
+    /// test to fall back on, each has to reach the limit or a cycle
+    fn check_fill(args: Args) {
+        let mut rend = traced(args);
+        let filled = std::mem::take(&mut rend.interior);
+        rend.functs.interior_test = Functions::no_interior_test;
+        let mut count = 0;
+        for (y, row) in filled.iter().enumerate() {
+            for (x, period) in row.iter().enumerate() {
+                if period.is_some() {
+                    let e = rend.sample(x as i32, y as i32, rend.pixel_delta(), (0.5, 0.5));
+                    assert!(e.i >= rend.args.limit, "{} {}: {}", x, y, e.i);
+                    count += 1;
+                }
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn filled_pixels_are_in_the_set() {
+        check_fill(Args::new());
+        check_fill(Args {
+            is_julia: true,
+            julia: Complex::new(-1.0, 0.0),
+            origin: Complex::new(0.0, 0.0),
+            ..Args::new()
+        });
+    }
+
+    #[test]
+    fn other_sets_are_not_filled() {
+        let mut args = Args::new();
+        args.fractal_mode = Formula::BurningShip;
+        assert!(traced(args).interior.is_empty());
+        let mut args = Args::new();
+        args.z_init = Complex::new(0.3, 0.0);
+        assert!(traced(args).interior.is_empty());
+    }
+}
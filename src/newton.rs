@@ -0,0 +1,208 @@
+// fracgen
+// Newton and Nova fractals
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Newton's method on the `--newton` polynomial, relaxed by `--relax`:
+//     z <- z - relax * p(z) / p'(z)
+// Each pixel is a starting point and is colored by the root it ends up at.
+// With `--nova` the pixel is added every step instead (or `--julia` is, for
+// `-i`), like `c` in the mandelbrot, and the orbit starts at a critical point
+// of the relaxed map moved by `--z-init`.
+// Orbits stop once a step is shorter than `--tolerance` instead of escaping.
+
+use num::complex::Complex;
+
+use crate::{iterdata::Sample, renderer::Renderer};
+
+type Cf64 = Complex<f64>;
+
+/// Steps of the root finder, far more than any reasonable degree needs
+const ROOT_STEPS: usize = 1000;
+
+/// `p(z)` and `p'(z)` of the polynomial with `coeffs`, highest degree first
+pub fn horner(coeffs: &[Cf64], z: Cf64) -> (Cf64, Cf64) {
+    let zero = Cf64::new(0.0, 0.0);
+    coeffs
+        .iter()
+        .fold((zero, zero), |(p, dp), a| (p * z + a, dp * z + p))
+}
+
+/// Every root of the polynomial with `coeffs`, found all at once with the
+/// Durand-Kerner method
+pub fn roots(coeffs: &[Cf64]) -> Vec<Cf64> {
+    // leading zeros don't add to the degree
+    let lead = match coeffs.iter().find(|a| a.norm_sqr() > 0.0) {
+        Some(lead) => *lead,
+        None => return Vec::new(),
+    };
+    let coeffs: Vec<Cf64> = coeffs
+        .iter()
+        .skip_while(|a| a.norm_sqr() == 0.0)
+        .map(|a| a / lead)
+        .collect();
+    let degree = coeffs.len().saturating_sub(1);
+    // powers of a number that is neither real nor a root of unity
+    let seed = Cf64::new(0.4, 0.9);
+    let mut roots: Vec<Cf64> = (0..degree).map(|k| seed.powu(k as u32)).collect();
+    for _ in 0..ROOT_STEPS {
+        for k in 0..degree {
+            let (p, _) = horner(&coeffs, roots[k]);
+            let q = (0..degree)
+                .filter(|j| *j != k)
+                .fold(Cf64::new(1.0, 0.0), |q, j| q * (roots[k] - roots[j]));
+            roots[k] -= p / q;
+        }
+    }
+    roots
+}
+
+/// Coefficients of the derivative of the polynomial with `coeffs`
+fn derivative(coeffs: &[Cf64]) -> Vec<Cf64> {
+    let n = coeffs.len().saturating_sub(1);
+    coeffs[..n]
+        .iter()
+        .enumerate()
+        .map(|(k, a)| a * (n - k) as f64)
+        .collect()
+}
+
+/// Coefficients of the product of two polynomials
+fn product(a: &[Cf64], b: &[Cf64]) -> Vec<Cf64> {
+    let mut out = vec![Cf64::new(0.0, 0.0); (a.len() + b.len()).saturating_sub(1)];
+    for (j, x) in a.iter().enumerate() {
+        for (k, y) in b.iter().enumerate() {
+            out[j + k] += x * y;
+        }
+    }
+    out
+}
+
+/// A critical point of the relaxed Newton map of `coeffs`, where the
+/// `--nova` orbits start (z = 1 for z³ - 1). Its derivative
+///     1 - relax + relax * p p'' / p'²
+/// is 0 where `(1 - relax) p'² + relax p p''` is, leaving out the zeros of
+/// p', where the map isn't defined. A map without one starts at 0
+pub fn critical_point(coeffs: &[Cf64], relax: Cf64) -> Cf64 {
+    let (d1, d2) = (derivative(coeffs), derivative(&derivative(coeffs)));
+    let (a, b) = (product(&d1, &d1), product(coeffs, &d2));
+    // both have degree 2n - 2, so they line up from the constant term
+    let len = a.len().max(b.len());
+    let pad = |v: &[Cf64], k: usize| match k + v.len() >= len {
+        true => v[k + v.len() - len],
+        false => Cf64::new(0.0, 0.0),
+    };
+    let q: Vec<Cf64> = (0..len)
+        .map(|k| (Cf64::new(1.0, 0.0) - relax) * pad(&a, k) + relax * pad(&b, k))
+        .collect();
+    let scale = coeffs.iter().map(|a| a.norm()).fold(0.0, f64::max);
+    roots(&q)
+        .into_iter()
+        .filter(|z| horner(&d1, *z).0.norm() > 1e-6 * scale.max(1.0))
+        .max_by(|a, b| a.re.total_cmp(&b.re))
+        .unwrap_or(Cf64::new(0.0, 0.0))
+}
+
+impl Renderer {
+    /// Runs Newton's method from the pixel point `c`. `der` of the result
+    /// is the last step and `s` a smoothed step count
+    pub fn newton_sample(&self, c: Cf64) -> Sample {
+        let coeffs = &self.args.newton;
+        let (mut z, add) = if self.args.nova {
+            let add = match self.args.is_julia {
+                true => (self.functs.cmap_funct)(self.args.julia),
+                false => c,
+            };
+            let start = self.args.z_init + self.functs.nova_start;
+            ((self.functs.init_funct)(start, c), add)
+        } else {
+            (c, Cf64::new(0.0, 0.0))
+        };
+        let mut step = Cf64::new(f64::INFINITY, 0.0);
+        let mut i = 0.0;
+        while (self.functs.conditional)(self, z, step, step) && i < self.args.limit {
+            let (p, dp) = horner(coeffs, z);
+            let next = z - self.args.relax * p / dp + add;
+            step = next - z;
+            z = next;
+            i += 1.0;
+        }
+        if z.is_nan() || i >= self.args.limit {
+            return Sample {
+                i: self.args.limit,
+                s: self.args.limit,
+                z,
+                der: step,
+                period: 0,
+            };
+        }
+        // near a root every step squares the last, so how far the last one
+        // undershot the tolerance tells where between steps it was crossed
+        let tolerance = self.args.tolerance * self.args.tolerance;
+        let over = (step.norm_sqr().ln() / tolerance.ln()).log2();
+        Sample {
+            i,
+            s: i - over.clamp(0.0, 1.0),
+            z,
+            der: step,
+            period: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Args, Functs};
+
+    fn cubic() -> Vec<Cf64> {
+        [1.0, 0.0, 0.0, -1.0].map(|a| Cf64::new(a, 0.0)).to_vec()
+    }
+
+    #[test]
+    fn roots_of_unity_are_found() {
+        let roots = roots(&cubic());
+        assert_eq!(roots.len(), 3);
+        for k in 0..3 {
+            let root = Cf64::from_polar(1.0, k as f64 * 2.0 * std::f64::consts::PI / 3.0);
+            assert!(roots.iter().any(|r| (r - root).norm() < 1e-12), "{}", root);
+        }
+    }
+
+    #[test]
+    fn nova_starts_at_a_critical_point() {
+        assert!((critical_point(&cubic(), Cf64::new(1.0, 0.0)) - 1.0).norm() < 1e-12);
+        // where the relaxed map's derivative vanishes
+        let relax = Cf64::new(0.5, 0.2);
+        let z = critical_point(&cubic(), relax);
+        let (p, dp) = horner(&cubic(), z);
+        let d2 = Cf64::new(6.0, 0.0) * z;
+        let slope = Cf64::new(1.0, 0.0) - relax + relax * p * d2 / (dp * dp);
+        assert!(slope.norm() < 1e-9, "{}", slope);
+    }
+
+    #[test]
+    fn nova_is_not_all_set_color() {
+        let mut args = Args::new();
+        (args.width, args.height, args.limit) = (32, 24, 64.0);
+        (args.newton, args.nova, args.zoom) = (cubic(), true, 0.5);
+        let rend = Renderer::new(args.clone(), Functs::from_args(&args));
+        let d = rend.pixel_delta();
+        let escaped = (0..32 * 24)
+            .filter(|n| rend.sample(n % 32, n / 32, d, (0.5, 0.5)).i < args.limit)
+            .count();
+        assert!(escaped > 32 * 24 / 2, "{}", escaped);
+    }
+}
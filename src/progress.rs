@@ -0,0 +1,78 @@
+// fracgen
+// Progress reporting
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use linya::Progress;
+
+/// How often the bar is redrawn
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs `work` with a counter of finished items starting at `start`. Render
+/// threads only bump the counter, with `show` a separate thread redraws a bar
+/// of `total` items from it every `INTERVAL` until `work` returns
+pub fn with_progress<T>(
+    total: usize,
+    start: usize,
+    show: bool,
+    work: impl FnOnce(&AtomicUsize) -> T,
+) -> T {
+    let done = AtomicUsize::new(start);
+    if !show {
+        return work(&done);
+    }
+    let finished = AtomicBool::new(false);
+    thread::scope(|s| {
+        let reporter = s.spawn(|| {
+            let mut progress = Progress::new();
+            let bar = progress.bar(total, "");
+            loop {
+                // read before drawing so the last draw shows the final count
+                let last = finished.load(Ordering::Acquire);
+                progress.set_and_draw(&bar, done.load(Ordering::Relaxed).min(total));
+                if last {
+                    break;
+                }
+                thread::park_timeout(INTERVAL);
+            }
+        });
+        let out = work(&done);
+        finished.store(true, Ordering::Release);
+        reporter.thread().unpark();
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_sees_the_counter_from_start() {
+        for show in [false, true] {
+            let out = with_progress(10, 4, show, |done| {
+                done.fetch_add(6, Ordering::Relaxed);
+                done.load(Ordering::Relaxed)
+            });
+            assert_eq!(out, 10);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// fracgen
+// Tile pyramid export
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Every level of the pyramid is rendered at its own resolution: a level is
+// the same view with a smaller `--width`/`--height` (DZI) or a deeper
+// `--zoom` (XYZ), never a downsampled copy of the level above
+
+use std::{fmt, fs, io, path, str::FromStr, time::Instant};
+
+use crate::{
+    renderer::{Args, Functs},
+    serve::{tile_args, TILE},
+    tiled::{render_rect, save_tile},
+};
+
+/// DZI tiles are this big when `--tile` isn't given, so with the overlap
+/// on both sides most are 256 pixels
+const DZI_TILE: i32 = 254;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// `{name}.dzi` and `{name}_files/{level}/{col}_{row}.png`, level
+    /// `ceil(log2(max(width, height)))` is the full frame
+    Dzi,
+    /// `{z}/{x}/{y}.png` like `fracgen serve`, down to the level whose tiles
+    /// are at least as fine as the frame
+    Xyz,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "dzi" => Ok(Layout::Dzi),
+            "xyz" => Ok(Layout::Xyz),
+            _ => Err(format!(
+                "unknown pyramid layout {}, expected dzi or xyz",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Layout::Dzi => "dzi",
+            Layout::Xyz => "xyz",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Smallest `n` with `2^n >= x`
+fn levels_to(x: i32) -> u32 {
+    (x.max(1) as u32).next_power_of_two().trailing_zeros()
+}
+
+/// Renders the pyramid of `args`' frame into `dir`
+pub fn render_pyramid(args: &Args, functs: &Functs, layout: Layout, dir: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    match layout {
+        Layout::Dzi => render_dzi(args, functs, dir),
+        Layout::Xyz => render_xyz(args, functs, dir),
+    }
+}
+
+fn render_dzi(args: &Args, functs: &Functs, dir: &str) -> io::Result<()> {
+    let size = if args.tile > 0 { args.tile } else { DZI_TILE };
+    let overlap = args.overlap;
+    fs::write(
+        format!("{}{}{}.dzi", dir, path::MAIN_SEPARATOR, args.name),
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" Overlap=\"{}\" TileSize=\"{}\">\n  \
+             <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            overlap, size, args.width, args.height
+        ),
+    )?;
+    let max_level = levels_to(args.width.max(args.height));
+    for level in 0..=max_level {
+        let scale = 1 << (max_level - level);
+        // the same view, only with fewer pixels across it
+        let level_args = Args {
+            width: (args.width + scale - 1) / scale,
+            height: (args.height + scale - 1) / scale,
+            ..args.clone()
+        };
+        let (w, h) = (level_args.width, level_args.height);
+        let level_dir = format!(
+            "{}{}{}_files{}{}",
+            dir,
+            path::MAIN_SEPARATOR,
+            args.name,
+            path::MAIN_SEPARATOR,
+            level
+        );
+        fs::create_dir_all(&level_dir)?;
+        let (cols, rows) = ((w + size - 1) / size, (h + size - 1) / size);
+        for row in 0..rows {
+            for col in 0..cols {
+                let file = format!("{}{}{}_{}.png", level_dir, path::MAIN_SEPARATOR, col, row);
+                if args.resume && path::Path::new(&file).exists() {
+                    continue;
+                }
+                let now = Instant::now();
+                let x = (col * size - overlap).max(0);
+                let y = (row * size - overlap).max(0);
+                let x1 = ((col + 1) * size + overlap).min(w);
+                let y1 = ((row + 1) * size + overlap).min(h);
+                let rend = render_rect(&level_args, functs, x, y, x1 - x, y1 - y);
+                save_tile(&rend, &file)?;
+                println!(
+                    "level {}/{} tile {}/{} in {}ms",
+                    level,
+                    max_level,
+                    row * cols + col + 1,
+                    cols * rows,
+                    now.elapsed().as_millis()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_xyz(args: &Args, functs: &Functs, dir: &str) -> io::Result<()> {
+    let max_level = levels_to((args.width.max(args.height) + TILE - 1) / TILE);
+    for z in 0..=max_level {
+        let n = 1u32 << z;
+        for x in 0..n {
+            let col_dir = format!(
+                "{}{}{}{}{}",
+                dir,
+                path::MAIN_SEPARATOR,
+                z,
+                path::MAIN_SEPARATOR,
+                x
+            );
+            fs::create_dir_all(&col_dir)?;
+            for y in 0..n {
+                let file = format!("{}{}{}.png", col_dir, path::MAIN_SEPARATOR, y);
+                if args.resume && path::Path::new(&file).exists() {
+                    continue;
+                }
+                let now = Instant::now();
+                let rend = render_rect(&tile_args(args, z, x, y), functs, 0, 0, TILE, TILE);
+                save_tile(&rend, &file)?;
+                println!(
+                    "level {}/{} tile {}/{} in {}ms",
+                    z,
+                    max_level,
+                    x * n + y + 1,
+                    n * n,
+                    now.elapsed().as_millis()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_reach_the_frame() {
+        for (x, n) in [(1, 0), (2, 1), (3, 2), (256, 8), (257, 9)] {
+            assert_eq!(levels_to(x), n, "{}", x);
+        }
+        for layout in [Layout::Dzi, Layout::Xyz] {
+            assert_eq!(layout.to_string().parse::<Layout>(), Ok(layout));
+        }
+    }
+
+    #[test]
+    fn dzi_levels_have_every_tile() {
+        let dir = std::env::temp_dir().join(format!("fracgen-dzi-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let mut args = Args::new();
+        (args.width, args.height, args.limit) = (40, 30, 64.0);
+        (args.tile, args.overlap) = (16, 1);
+        render_pyramid(&args, &Functs::from_args(&args), Layout::Dzi, dir).unwrap();
+        let files = format!("{}/{}_files", dir, args.name);
+        // level 6 is the full 40x30 frame, level 0 a single pixel
+        let full = image::open(format!("{}/6/2_1.png", files)).unwrap();
+        assert_eq!((full.width(), full.height()), (40 - 31, 30 - 15));
+        assert!(!path::Path::new(&format!("{}/6/3_0.png", files)).exists());
+        let top = image::open(format!("{}/0/0_0.png", files)).unwrap();
+        assert_eq!((top.width(), top.height()), (1, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -18,37 +18,72 @@
 #![allow(incomplete_features, unused_imports, dead_code)]
 use clap::Parser;
 use image::{DynamicImage, ImageBuffer, Rgba};
-use linya::{Bar, Progress};
 use num::complex::Complex;
-use rand::Rng;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rayon::{
+    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
 use std::{
     f64::consts::PI,
+    ops::Range,
     path,
-    sync::Mutex,
+    sync::atomic::{AtomicUsize, Ordering},
     time::{Instant, SystemTime},
 };
 type Cf64 = Complex<f64>;
 type Img8 = ImageBuffer<Rgba<u8>, Vec<u8>>;
-use crate::color::{Color, ColorType};
+use crate::{
+    attractor::Attractor,
+    color::{Color, ColorType},
+    density::Tone,
+    filter::Filter,
+    formula::{Formula, Hybrid, State},
+    hyper::{Algebra, Hypercomplex, Slice},
+    iterdata::{IterData, Sample},
+    lyapunov::Sequence,
+    march::{March, Shape},
+    newton,
+    progress::with_progress,
+    pyramid::Layout,
+    sampler::{self, Pattern},
+    simd::{self, Kernel, LANES, PERIOD_EPS},
+};
 
 pub struct Functions;
 
 impl Functions {
     #[allow(dead_code, unused_variables)]
     pub fn default_bail(rend: &Renderer, z: Cf64, der: Cf64, der_sum: Cf64) -> bool {
-        z.norm_sqr() < rend.args.bail
+        z.norm_sqr() < rend.functs.bail
     }
 
     #[allow(dead_code, unused_variables)]
     pub fn sqrt_bail(rend: &Renderer, z: Cf64, der: Cf64, der_sum: Cf64) -> bool {
-        z.norm() < rend.args.bail
+        z.norm() < rend.functs.bail
     }
 
     #[allow(dead_code, unused_variables)]
     pub fn der_bail(rend: &Renderer, z: Cf64, der: Cf64, der_sum: Cf64) -> bool {
         (der_sum * der_sum).norm_sqr() < rend.args.derbail
-            && z.norm_sqr() * z.norm_sqr() < rend.args.bail
+            && z.norm_sqr() * z.norm_sqr() < rend.functs.bail
+    }
+
+    /// Newton's method goes on while the last step, passed as `der`, is
+    /// longer than `--tolerance`
+    #[allow(dead_code, unused_variables)]
+    pub fn converge_bail(rend: &Renderer, z: Cf64, der: Cf64, der_sum: Cf64) -> bool {
+        der.norm_sqr() >= rend.args.tolerance * rend.args.tolerance
+    }
+
+    /// `hue` turned by `--phase`, palettes that pick a hue use this as their
+    /// position. Left alone without a phase so hues past 360 aren't rounded
+    pub fn phased_hue(rend: &Renderer, hue: f64) -> f64 {
+        if rend.args.phase == 0.0 {
+            hue
+        } else {
+            (hue + rend.args.phase).rem_euclid(360.0)
+        }
     }
 
     #[allow(dead_code, unused_variables)]
@@ -56,6 +91,7 @@ impl Functions {
         let hue = ((1.0 - (s / rend.args.limit)) * 360.0)
             .powf(rend.args.cexp)
             .powf(1.5);
+        let hue = Functions::phased_hue(rend, hue);
         Color::new([hue, 1.0, 1.0, 1.0], ColorType::Hsva).to_Rgba()
     }
 
@@ -67,10 +103,11 @@ impl Functions {
         let sat = (4096.0 / 360.0 * PI * iter_count).cos() / 2.0 + 0.5;
         let val = 1.0 - (2048.0 / 360.0 * PI * iter_count).sin() / 2.0 - 0.5;
 
-        // # convert u into rgb of hue cycle
-        let mut r = (((1.0 - 2.0 * (iter_count).cos()) / 2.0).max(0.0)).min(1.0);
-        let mut g = (((1.0 - 2.0 * (iter_count + PI * 2.0 / 3.0).cos()) / 2.0).max(0.0)).min(1.0);
-        let mut b = (((1.0 - 2.0 * (iter_count + PI * 4.0 / 3.0).cos()) / 2.0).max(0.0)).min(1.0);
+        // # convert u into rgb of hue cycle, `--phase` moves along it
+        let hue_count = iter_count + rend.args.phase.to_radians();
+        let mut r = ((1.0 - 2.0 * (hue_count).cos()) / 2.0).clamp(0.0, 1.0);
+        let mut g = ((1.0 - 2.0 * (hue_count + PI * 2.0 / 3.0).cos()) / 2.0).clamp(0.0, 1.0);
+        let mut b = ((1.0 - 2.0 * (hue_count + PI * 4.0 / 3.0).cos()) / 2.0).clamp(0.0, 1.0);
 
         // # apply saturation and brightness to the rgb
         r = ((1.0 + r * sat - sat) * val).sqrt();
@@ -85,15 +122,10 @@ impl Functions {
         );
         let normal_vec = z / der;
         let normal_vec = normal_vec / normal_vec.norm(); // abs norm_vec
-        let mut value =
-            ((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
-                / (1.0 + norm_height);
-        if value < 0.0 {
-            value = 0.0;
-        }
-        if value > 1.0 {
-            value = 1.0;
-        }
+        let value =
+            (((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
+                / (1.0 + norm_height))
+                .clamp(0.0, 1.0);
 
         // let hue = (((s / limit).powf(cexp)) * 360.0).powf(1.5);
         let mut color = Color::new([r, g, b, 1.0], ColorType::Rgba).to_Rgba();
@@ -110,10 +142,11 @@ impl Functions {
         let sat = 1.0;
         let val = 1.0 - (2048.0 / 360.0 * PI * iter_count).sin() / 2.0 - 0.5;
         let val = 1.0;
-        // # convert u into rgb of hue cycle
-        let mut r = (((1.0 - 2.0 * (iter_count).cos()) / 2.0).max(0.0)).min(1.0);
-        let mut g = (((1.0 - 2.0 * (iter_count + PI * 2.0 / 3.0).cos()) / 2.0).max(0.0)).min(1.0);
-        let mut b = (((1.0 - 2.0 * (iter_count + PI * 4.0 / 3.0).cos()) / 2.0).max(0.0)).min(1.0);
+        // # convert u into rgb of hue cycle, `--phase` moves along it
+        let hue_count = iter_count + rend.args.phase.to_radians();
+        let mut r = ((1.0 - 2.0 * (hue_count).cos()) / 2.0).clamp(0.0, 1.0);
+        let mut g = ((1.0 - 2.0 * (hue_count + PI * 2.0 / 3.0).cos()) / 2.0).clamp(0.0, 1.0);
+        let mut b = ((1.0 - 2.0 * (hue_count + PI * 4.0 / 3.0).cos()) / 2.0).clamp(0.0, 1.0);
 
         // # apply saturation and brightness to the rgb
         r = ((1.0 + r * sat - sat) * val).sqrt();
@@ -128,15 +161,10 @@ impl Functions {
         );
         let normal_vec = z / der;
         let normal_vec = normal_vec / normal_vec.norm(); // abs norm_vec
-        let mut value =
-            ((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
-                / (1.0 + norm_height);
-        if value < 0.0 {
-            value = 0.0;
-        }
-        if value > 1.0 {
-            value = 1.0;
-        }
+        let value =
+            (((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
+                / (1.0 + norm_height))
+                .clamp(0.0, 1.0);
 
         // let hue = (((s / limit).powf(cexp)) * 360.0).powf(1.5);
         let mut color = Color::new([r, g, b, 1.0], ColorType::Rgba).to_Rgba();
@@ -144,6 +172,73 @@ impl Functions {
         color
     }
 
+    /// The escape count made continuous: `ln ln |z|` grows by `ln degree` a
+    /// step once z is large, so its fraction of a step is taken off `i`.
+    /// Formulas without a degree keep the plain count
+    pub fn smooth_count(rend: &Renderer, i: f64, z: Cf64) -> f64 {
+        match rend.functs.degree {
+            Some(d) if d > 1.0 && z.norm_sqr() > 1.0 => i + 1.0 - z.norm().ln().ln() / d.ln(),
+            _ => i,
+        }
+    }
+
+    /// A hue cycle every 36 steps of the smooth count
+    #[allow(dead_code, unused_variables)]
+    pub fn smooth_coloring(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
+        let count = Functions::smooth_count(rend, i, z).max(0.0);
+        let hue = Functions::phased_hue(rend, count.powf(rend.args.cexp) * 10.0);
+        Color::from_hsv(hue, 0.8, 1.0, 1.0)
+    }
+
+    /// The smooth count's hue, darkened near the set by the exterior distance
+    /// estimate `|z| ln |z| / |z'|` measured in pixels
+    #[allow(dead_code, unused_variables)]
+    pub fn distance_coloring(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
+        let r = z.norm();
+        let distance = match r > 1.0 && der.norm_sqr() > 0.0 {
+            true => r * r.ln() / der.norm(),
+            false => 0.0,
+        };
+        let pixel = rend.pixel_delta().re.abs();
+        let value = (distance / pixel).min(1.0).powf(0.25 * rend.args.cexp);
+        let count = Functions::smooth_count(rend, i, z).max(0.0);
+        let hue = Functions::phased_hue(rend, count * 10.0);
+        Color::from_hsv(hue, 0.8, value, 1.0)
+    }
+
+    /// A hue per root of the `--newton` polynomial (or by the angle of where
+    /// a nova orbit settled), darker the more steps it took to get there
+    #[allow(dead_code, unused_variables)]
+    pub fn root_coloring(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
+        let roots = &rend.functs.roots;
+        let nearest = (0..roots.len()).min_by(|a, b| {
+            (z - roots[*a])
+                .norm_sqr()
+                .total_cmp(&(z - roots[*b]).norm_sqr())
+        });
+        let hue = match nearest {
+            Some(k) => 360.0 * k as f64 / roots.len() as f64,
+            None => z.arg().to_degrees() + 180.0,
+        };
+        let hue = Functions::phased_hue(rend, hue);
+        let value = (1.0 / (1.0 + s / 16.0)).powf(rend.args.cexp);
+        Color::from_hsv(hue, 0.75, value, 1.0)
+    }
+
+    /// Stable regions (`s`, the Lyapunov exponent, below 0) in gold, chaotic
+    /// ones in blue, both fading to black where the exponent is near 0
+    #[allow(dead_code, unused_variables)]
+    pub fn lyapunov_coloring(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
+        if s < 0.0 {
+            let value = (1.0 - s.exp()).powf(rend.args.cexp);
+            Color::from_hsv(Functions::phased_hue(rend, 45.0), 0.85, value, 1.0)
+        } else {
+            // the logistic map's exponent is at most ln 2
+            let value = (s / std::f64::consts::LN_2).min(1.0).powf(rend.args.cexp);
+            Color::from_hsv(Functions::phased_hue(rend, 220.0), 0.8, value, 1.0)
+        }
+    }
+
     #[allow(dead_code, unused_variables)]
     pub fn normal_map(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
         let light_deg = 270f64;
@@ -154,25 +249,22 @@ impl Functions {
         );
         let normal_vec = z / der;
         let normal_vec = normal_vec / normal_vec.norm(); // abs norm_vec
-        let mut value =
-            ((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
-                / (1.0 + norm_height);
-        if value < 0.0 {
-            value = 0.0;
-        }
-        if value > 1.0 {
-            value = 1.0;
-        }
+        let value =
+            (((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
+                / (1.0 + norm_height))
+                .clamp(0.0, 1.0);
         let hue = (((s / rend.args.limit).powf(rend.args.cexp)) * 360.0).powf(1.5);
+        let hue = Functions::phased_hue(rend, hue);
         Color::new([hue, 1.0, value, 1.0], ColorType::Hsva).to_Rgba()
     }
 
     #[allow(dead_code, unused_variables)]
     pub fn image_mapping(rend: &Renderer, i: f64, s: f64, z: Cf64, der: Cf64) -> Color {
         let (w, h) = (rend.texture.width(), rend.texture.height());
-        let width = ((z.im.atan2(z.re) + PI) / (PI * 2.0) * w as f64).round() as u32 % w;
+        let turn = ((z.im.atan2(z.re) + PI) / (PI * 2.0) + rend.args.phase / 360.0).rem_euclid(1.0);
+        let width = (turn * w as f64).round() as u32 % w;
         let height = (h as f64 - 1.0f64)
-            - ((z.norm() / rend.args.bail).log(rend.args.bail) * (h as f64 - 1.0f64)).floor();
+            - ((z.norm() / rend.functs.bail).log(rend.functs.bail) * (h as f64 - 1.0f64)).floor();
         let mut height = ((height as u32) * 2) % h;
         if i as u32 % 2 == 1 {
             height = (h - 1) - height;
@@ -194,15 +286,10 @@ impl Functions {
         );
         let normal_vec = z / der;
         let normal_vec = normal_vec / normal_vec.norm(); // abs norm_vec
-        let mut value =
-            ((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
-                / (1.0 + norm_height);
-        if value < 0.0 {
-            value = 0.0;
-        }
-        if value > 1.0 {
-            value = 1.0;
-        }
+        let value =
+            (((normal_vec.re * light_vec.re) + (normal_vec.im * light_vec.im) + norm_height)
+                / (1.0 + norm_height))
+                .clamp(0.0, 1.0);
 
         color.ch[2] *= value;
         color.to_Rgba()
@@ -233,10 +320,29 @@ impl Functions {
     pub fn mandelbrot(z: Cf64, c: Cf64, j: Cf64) -> Cf64 {
         z * z + c
     }
+
+    #[allow(dead_code, unused_variables)]
+    pub fn no_interior_test(c: Cf64) -> Option<u32> {
+        None
+    }
+
+    /// Closed form tests for the main cardioid and the period 2 bulb of
+    /// `z^2 + c`, returns the period of the points inside them
+    pub fn bulb_test(c: Cf64) -> Option<u32> {
+        let x = c.re - 0.25;
+        let q = x * x + c.im * c.im;
+        if q * (q + x) <= 0.25 * c.im * c.im {
+            Some(1)
+        } else if (c.re + 1.0) * (c.re + 1.0) + c.im * c.im <= 0.0625 {
+            Some(2)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone, Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_override_self = true)]
 pub struct Args {
     #[clap(long, default_value = "1920")]
     pub width: i32,
@@ -265,7 +371,9 @@ pub struct Args {
     #[clap(long, default_value = "4")]
     pub samples: usize,
 
-    #[clap(long, default_value = "20")]
+    /// No longer used, cycles are found with Brent's algorithm. Kept so
+    /// older scene files still parse
+    #[clap(long, default_value = "20", hide = true)]
     pub cycles: usize,
 
     #[clap(long, default_value = "2.0")]
@@ -274,8 +382,9 @@ pub struct Args {
     #[clap(long, default_value = "1024.0")]
     pub limit: f64,
 
-    #[clap(long, default_value = "64.0")]
-    pub bail: f64,
+    /// Squared escape radius, the formula's own when not given
+    #[clap(long)]
+    pub bail: Option<f64>,
 
     #[clap(long, default_value = "16384.0")]
     pub derbail: f64,
@@ -289,14 +398,209 @@ pub struct Args {
     #[clap(short)]
     pub is_julia: bool,
 
-    #[clap(long, default_value = "0")]
-    pub fractal_mode: usize,
+    #[clap(long, default_value = "mandelbrot")]
+    pub fractal_mode: Formula,
+
+    /// Formulas iterated in turn instead of `--fractal-mode`
+    #[clap(long)]
+    pub hybrid: Option<Hybrid>,
 
     #[clap(long, default_value = "0")]
     pub color_mode: usize,
 
     #[clap(long, default_value = "0")]
     pub bail_mode: usize,
+
+    #[clap(long, default_value = "0.0")]
+    pub phase: f64,
+
+    #[clap(long)]
+    pub save_iters: Option<String>,
+
+    #[clap(long)]
+    pub cycle: Option<String>,
+
+    #[clap(long, default_value = "60")]
+    pub frames: usize,
+
+    #[clap(long)]
+    pub gif: bool,
+
+    #[clap(long, default_value = "0")]
+    pub tile: i32,
+
+    #[clap(long)]
+    pub keep_tiles: bool,
+
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    #[clap(long)]
+    pub checkpoint: Option<String>,
+
+    #[clap(long, default_value = "300")]
+    pub checkpoint_secs: u64,
+
+    #[clap(long)]
+    pub resume: bool,
+
+    #[clap(long)]
+    pub accum: Option<String>,
+
+    #[clap(long)]
+    pub add_samples: Option<usize>,
+
+    #[clap(long, value_delimiter = ',')]
+    pub merge: Vec<String>,
+
+    #[clap(long)]
+    pub listen: Option<String>,
+
+    #[clap(long)]
+    pub connect: Option<String>,
+
+    #[clap(long)]
+    pub send_iters: bool,
+
+    /// Seconds the coordinator waits on a worker's tile before handing it
+    /// to someone else
+    #[clap(long, default_value = "600")]
+    pub worker_timeout: u64,
+
+    #[clap(long, default_value = "0.0")]
+    pub noise: f64,
+
+    #[clap(long, default_value = "64")]
+    pub max_samples: usize,
+
+    #[clap(long, default_value = "4")]
+    pub adaptive_step: usize,
+
+    #[clap(long)]
+    pub heatmap: bool,
+
+    #[clap(long, default_value = "random")]
+    pub pattern: Pattern,
+
+    #[clap(long, default_value = "box")]
+    pub filter: Filter,
+
+    #[clap(long, default_value = "0.5")]
+    pub filter_radius: f64,
+
+    #[clap(long)]
+    pub mariani: bool,
+
+    #[clap(long)]
+    pub period_color: bool,
+
+    #[clap(long)]
+    pub scalar: bool,
+
+    #[clap(long)]
+    pub check_kernel: bool,
+
+    #[clap(long)]
+    pub scene: Option<String>,
+
+    #[clap(long, default_value = "1024")]
+    pub cache_tiles: usize,
+
+    #[clap(long)]
+    pub pyramid: Option<Layout>,
+
+    #[clap(long, default_value = "1")]
+    pub overlap: i32,
+
+    #[clap(long)]
+    pub buddha: bool,
+
+    #[clap(long)]
+    pub anti: bool,
+
+    #[clap(long, value_delimiter = ',')]
+    pub nebula: Vec<f64>,
+
+    #[clap(long)]
+    pub metropolis: bool,
+
+    #[clap(long)]
+    pub tone: Option<Tone>,
+
+    #[clap(long, value_delimiter = ',')]
+    pub newton: Vec<Cf64>,
+
+    #[clap(long)]
+    pub nova: bool,
+
+    #[clap(long, default_value_t=Complex::<f64>::new(1.0,0.0))]
+    pub relax: Cf64,
+
+    #[clap(long, default_value = "1e-6")]
+    pub tolerance: f64,
+
+    #[clap(long)]
+    pub lyapunov: Option<Sequence>,
+
+    #[clap(long)]
+    pub attractor: Option<Attractor>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub params: Vec<f64>,
+
+    #[clap(long)]
+    pub age: bool,
+
+    #[clap(long)]
+    pub flame: Option<String>,
+
+    #[clap(long)]
+    pub hyper: Option<Hypercomplex>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub slice: Vec<f64>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub rotate: Vec<f64>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub hyper_julia: Vec<f64>,
+
+    #[clap(long)]
+    pub march: Option<Shape>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub camera: Vec<f64>,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub look_at: Vec<f64>,
+
+    #[clap(long, default_value = "60")]
+    pub fov: f64,
+
+    #[clap(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub light: Vec<f64>,
+
+    #[clap(long, default_value = "8")]
+    pub shadow: f64,
+
+    #[clap(long, default_value = "1")]
+    pub ao: f64,
+
+    #[clap(long, default_value = "0")]
+    pub fog: f64,
+
+    #[clap(long, default_value = "0")]
+    pub glow: f64,
+
+    #[clap(long)]
+    pub de_iters: Option<usize>,
+
+    #[clap(long)]
+    pub depth: bool,
+
+    #[clap(long)]
+    pub normals: bool,
 }
 
 impl Args {
@@ -314,16 +618,118 @@ impl Args {
             samples: 4,
             sampled: 2.0,
             limit: 1024.0,
-            bail: 256.0,
+            bail: None,
             derbail: 16384.0,
             cexp: 1.0,
             is_julia: false,
-            fractal_mode: 0,
+            fractal_mode: Formula::Mandelbrot,
+            hybrid: None,
             color_mode: 0,
             bail_mode: 0,
+            phase: 0.0,
+            save_iters: None,
+            cycle: None,
+            frames: 60,
+            gif: false,
+            tile: 0,
+            keep_tiles: false,
+            seed: None,
+            checkpoint: None,
+            checkpoint_secs: 300,
+            resume: false,
+            accum: None,
+            add_samples: None,
+            merge: Vec::new(),
+            listen: None,
+            connect: None,
+            send_iters: false,
+            worker_timeout: 600,
+            noise: 0.0,
+            max_samples: 64,
+            adaptive_step: 4,
+            heatmap: false,
+            pattern: Pattern::Random,
+            filter: Filter::Box,
+            filter_radius: 0.5,
+            mariani: false,
+            period_color: false,
+            scalar: false,
+            check_kernel: false,
+            scene: None,
+            cache_tiles: 1024,
+            pyramid: None,
+            overlap: 1,
+            buddha: false,
+            anti: false,
+            nebula: Vec::new(),
+            metropolis: false,
+            tone: None,
+            newton: Vec::new(),
+            nova: false,
+            relax: Complex::<f64>::new(1.0, 0.0),
+            tolerance: 1e-6,
+            lyapunov: None,
+            attractor: None,
+            params: Vec::new(),
+            age: false,
+            flame: None,
+            hyper: None,
+            slice: Vec::new(),
+            rotate: Vec::new(),
+            hyper_julia: Vec::new(),
+            march: None,
+            camera: Vec::new(),
+            look_at: Vec::new(),
+            fov: 60.0,
+            light: Vec::new(),
+            shadow: 8.0,
+            ao: 1.0,
+            fog: 0.0,
+            glow: 0.0,
+            de_iters: None,
+            depth: false,
+            normals: false,
             set_color: Color::new([0.0, 0.0, 0.0, 1.0], ColorType::Rgba),
         }
     }
+
+    /// The formula of every step of one pass through `--hybrid`, or just
+    /// `--fractal-mode`
+    pub fn formulas(&self) -> Vec<Formula> {
+        match &self.hybrid {
+            Some(hybrid) => hybrid.steps(),
+            None => vec![self.fractal_mode],
+        }
+    }
+
+    /// `--bail`, or the square of the widest escape radius of the formulas
+    pub fn bail(&self) -> f64 {
+        self.bail.unwrap_or_else(|| {
+            let radius = self
+                .formulas()
+                .iter()
+                .fold(0.0f64, |r, f| r.max(f.radius()));
+            radius * radius
+        })
+    }
+
+    /// The formulas' parameter, from `--params` or the defaults of the first
+    /// formula that takes one
+    pub fn param(&self) -> Cf64 {
+        let params = match self.params.len() {
+            0 => self
+                .formulas()
+                .iter()
+                .map(Formula::default_params)
+                .find(|p| !p.is_empty())
+                .unwrap_or_default(),
+            _ => self.params.clone(),
+        };
+        Cf64::new(
+            params.first().copied().unwrap_or(0.0),
+            params.get(1).copied().unwrap_or(0.0),
+        )
+    }
 }
 
 impl Default for Args {
@@ -344,30 +750,267 @@ pub fn normalize_coords(x: i32, y: i32, w: i32, h: i32, z: f64) -> Cf64 {
 
 #[derive(Clone)]
 pub struct Functs {
-    pub iter_funct: fn(Cf64, Cf64, Cf64) -> Cf64,
+    /// the formula of each step, cycled through
+    pub formulas: Vec<Formula>,
+    /// the formulas' parameter, see `Args::param`
+    pub param: Cf64,
+    /// the squared escape radius, `Args::bail`
+    pub bail: f64,
+    /// how fast orbits grow per step once they escape, the formulas' mean
+    /// degree, `None` if one of them has none
+    pub degree: Option<f64>,
     pub init_funct: fn(Cf64, Cf64) -> Cf64,
     pub cmap_funct: fn(Cf64) -> Cf64,
     pub color_funct: fn(&Renderer, f64, f64, Cf64, Cf64) -> Color,
     pub conditional: fn(&Renderer, Cf64, Cf64, Cf64) -> bool,
+    /// Decides from `c` alone that a point never escapes, and its period
+    pub interior_test: fn(Cf64) -> Option<u32>,
+    /// Batched version of the iteration above, only set for built-in formulas
+    pub kernel: Option<Kernel>,
+    /// roots of the `--newton` polynomial, for `root_coloring`
+    pub roots: Vec<Cf64>,
+    /// where `--nova` orbits start before `--z-init`, see `newton::critical_point`
+    pub nova_start: Cf64,
+    /// the plane through 4D space `--hyper` renders
+    pub slice: Option<Slice>,
+    /// the camera and lighting of `--march`
+    pub march: Option<March>,
 }
 
 impl Functs {
     pub fn new(
-        a: fn(Cf64, Cf64, Cf64) -> Cf64,
+        a: Formula,
         b: fn(Cf64, Cf64) -> Cf64,
         c: fn(Cf64) -> Cf64,
         d: fn(&Renderer, f64, f64, Cf64, Cf64) -> Color,
         e: fn(&Renderer, Cf64, Cf64, Cf64) -> bool,
     ) -> Functs {
         Functs {
-            iter_funct: a,
+            formulas: vec![a],
+            param: Cf64::new(0.0, 0.0),
+            bail: a.radius() * a.radius(),
+            degree: a.degree(Cf64::new(0.0, 0.0)),
             init_funct: b,
             cmap_funct: c,
             color_funct: d,
             conditional: e,
+            interior_test: Functions::no_interior_test,
+            kernel: None,
+            roots: Vec::new(),
+            nova_start: Cf64::new(0.0, 0.0),
+            slice: None,
+            march: None,
+        }
+    }
+
+    /// The iteration, coloring and bailout functions `args` selects
+    pub fn from_args(args: &Args) -> Functs {
+        let mut functs = Functs::new(
+            args.fractal_mode,
+            // julia sets start the orbit at the pixel, the mandelbrot at `--z-init`
+            match args.is_julia {
+                true => |_, c| c,
+                false => |z, _| z,
+            },
+            Functions::identity,
+            match args.color_mode {
+                0 => Functions::coloring,
+                1 => Functions::normal_map,
+                2 => Functions::miles_coloring,
+                3 => Functions::miles_coloring2,
+                4 => Functions::smooth_coloring,
+                5 => Functions::distance_coloring,
+                _ => Functions::coloring,
+            },
+            match args.bail_mode {
+                0 => Functions::default_bail,
+                1 => Functions::sqrt_bail,
+                2 => Functions::der_bail,
+                _ => Functions::default_bail,
+            },
+        );
+        functs.formulas = args.formulas();
+        functs.param = args.param();
+        functs.bail = args.bail();
+        // the geometric mean, a hybrid's orbit grows by the product of its steps
+        let degrees = functs.formulas.iter().map(|f| f.degree(functs.param));
+        functs.degree = degrees
+            .product::<Option<f64>>()
+            .map(|product| product.powf(1.0 / functs.formulas.len() as f64));
+        // the bulb tests only hold for the plain mandelbrot started at 0
+        let quadratic = functs.formulas == [Formula::Mandelbrot];
+        if quadratic && !args.is_julia && args.z_init == Cf64::new(0.0, 0.0) {
+            functs.interior_test = Functions::bulb_test;
         }
+        let formula = match functs.formulas[..] {
+            [f @ (Formula::Mandelbrot | Formula::BurningShip)] => Some(f),
+            _ => None,
+        };
+        let der_bail = match args.bail_mode {
+            1 => None,
+            2 => Some(true),
+            _ => Some(false),
+        };
+        if let (Some(formula), Some(der_bail)) = (formula, der_bail) {
+            functs.kernel = Some(Kernel {
+                formula,
+                der_bail,
+                julia: args.is_julia,
+            });
+        }
+        if !args.newton.is_empty() {
+            functs.conditional = Functions::converge_bail;
+            if args.color_mode == 0 {
+                functs.color_funct = Functions::root_coloring;
+            }
+            functs.interior_test = Functions::no_interior_test;
+            functs.kernel = None;
+            match args.nova {
+                true => functs.nova_start = newton::critical_point(&args.newton, args.relax),
+                false => functs.roots = newton::roots(&args.newton),
+            }
+        }
+        if args.lyapunov.is_some() {
+            if args.color_mode == 0 {
+                functs.color_funct = Functions::lyapunov_coloring;
+            }
+            functs.interior_test = Functions::no_interior_test;
+            functs.kernel = None;
+        }
+        functs.slice = Slice::from_args(args);
+        if functs.slice.is_some() {
+            functs.interior_test = Functions::no_interior_test;
+            functs.kernel = None;
+        }
+        functs.march = March::from_args(args);
+        if functs.march.is_some() {
+            functs.interior_test = Functions::no_interior_test;
+            functs.kernel = None;
+        }
+        functs
     }
+
+    /// The formula of step `n` of an orbit
+    pub fn formula(&self, n: usize) -> Formula {
+        self.formulas[n % self.formulas.len()]
+    }
+
+    /// The state the orbit of `c` starts in, `z_init` is added to the first
+    /// formula's critical point for mandelbrot style sets. `k` is what the
+    /// orbit adds each step, a constant for `julia` sets
+    pub fn start(&self, z_init: Cf64, c: Cf64, k: Cf64, julia: bool) -> State {
+        let formula = self.formula(0);
+        formula.state((self.init_funct)(z_init + formula.critical(c), c), k, julia)
+    }
+
+    /// Whether orbit state `a` is back at `b` within `eps` (squared), so the
+    /// orbit repeats from there. The next steps depend on z, the z before it
+    /// for phoenix and manowar, spider's moving k and where in the hybrid the
+    /// orbit is, the other `aux` values are derivatives
+    pub fn repeats(&self, a: &State, b: &State, eps: f64) -> bool {
+        let near = |u: Cf64, v: Cf64| (u - v).norm_sqr() < eps;
+        let uses = |f: Formula| self.formulas.contains(&f);
+        let len = self.formulas.len();
+        a.n % len == b.n % len
+            && near(a.z, b.z)
+            && (!(uses(Formula::Phoenix) || uses(Formula::Manowar)) || near(a.prev, b.prev))
+            && (!uses(Formula::Spider) || near(a.aux[0], b.aux[0]))
+    }
+
+    /// `c` or `--julia`, whichever the orbit of `c` adds each step
+    pub fn added(&self, args: &Args, c: Cf64) -> Cf64 {
+        match args.is_julia {
+            true => (self.cmap_funct)(args.julia),
+            false => c,
+        }
+    }
+}
+/// An orbit `iterate` can step, in the plane or in a `--hyper` algebra
+pub trait Orbit: Copy {
+    type Num: Algebra;
+    fn z(&self) -> Self::Num;
+    /// derivative of z by the pixel
+    fn der(&self) -> Self::Num;
+    fn step(&mut self);
+    /// Whether the orbit is back where `other` was, within `eps` (squared)
+    fn repeats(&self, other: &Self, eps: f64) -> bool;
 }
+
+/// The `--fractal-mode` or `--hybrid` orbit of a pixel
+#[derive(Clone, Copy)]
+struct PlaneOrbit<'a> {
+    functs: &'a Functs,
+    state: State,
+    k: Cf64,
+}
+
+impl Orbit for PlaneOrbit<'_> {
+    type Num = Cf64;
+    fn z(&self) -> Cf64 {
+        self.state.z
+    }
+    fn der(&self) -> Cf64 {
+        self.state.der
+    }
+    fn step(&mut self) {
+        let formula = self.functs.formula(self.state.n);
+        formula.step(&mut self.state, self.k, self.functs.param);
+    }
+    fn repeats(&self, other: &Self, eps: f64) -> bool {
+        self.functs.repeats(&self.state, &other.state, eps)
+    }
+}
+
+/// Steps `orbit` while `inside` says its z, derivative and the sum of the
+/// derivatives so far haven't escaped, for `limit` steps at most. The
+/// sample's z and derivative are the first two components of the orbit's,
+/// which is handed back for callers that need all of them
+pub fn iterate<O: Orbit>(
+    mut orbit: O,
+    limit: f64,
+    inside: impl Fn(O::Num, O::Num, O::Num) -> bool,
+) -> (Sample, O) {
+    let one = O::Num::from_parts([1.0, 0.0, 0.0, 0.0]);
+    let (mut i, mut s) = (0.0, 0.0);
+    let mut tot_der = one;
+    // Brent's cycle detection: `check` jumps to the orbit every `power`
+    // steps (doubling each time), meeting it again within `lam` steps
+    // means the orbit is in a cycle of length `lam`
+    let mut check = orbit;
+    let (mut power, mut lam) = (1u32, 0u32);
+    let mut period = 0;
+    while inside(orbit.z(), orbit.der(), tot_der) && i < limit {
+        tot_der = tot_der + orbit.der();
+        orbit.step();
+        let z = orbit.z();
+        i += 1.0;
+        s += (-(z + one).norm_sqr()).exp();
+
+        lam += 1;
+        let eps = PERIOD_EPS * PERIOD_EPS * z.norm_sqr().max(1.0);
+        if orbit.repeats(&check, eps) {
+            i = limit;
+            s = limit;
+            period = lam;
+            break;
+        }
+        if lam == power {
+            check = orbit;
+            power *= 2;
+            lam = 0;
+        }
+    }
+    let plane = |v: O::Num| Cf64::new(v.parts()[0], v.parts()[1]);
+    let sample = Sample {
+        i,
+        s,
+        z: plane(orbit.z()),
+        der: plane(orbit.der()),
+        period,
+    };
+    (sample, orbit)
+}
+
 pub struct Renderer {
     pub args: Args,
     pub width: i32,
@@ -375,26 +1018,79 @@ pub struct Renderer {
     pub functs: Functs,
     pub image: Img8,
     pub raw: Vec<Vec<Color>>,
+    /// Empty without `--noise`
+    pub raw_sq: Vec<Vec<Color>>,
+    /// Empty without `--noise`
+    pub extra_samples: Vec<Vec<usize>>,
+    /// Empty unless `--noise` or `--filter` make them differ, see `weight`
+    pub weights: Vec<Vec<f64>>,
+    pub interior: Vec<Vec<Option<u32>>>,
     pub rendered_samples: usize,
+    /// `--seed`s of the samples in the accumulator, see `merge_accum`
+    pub seeds: Vec<u64>,
     pub not_rendering: bool,
     pub texture: Img8,
+    pub offset: (i32, i32),
 }
 
 impl Renderer {
     pub fn new(args: Args, functs: Functs) -> Renderer {
-        Renderer {
-            args: args.clone(),
-            width: args.width,
-            height: args.height,
+        let (w, h) = (args.width, args.height);
+        Renderer::tile(args, functs, 0, 0, w, h)
+    }
+
+    /// A renderer for the `w`x`h` window at (`x`, `y`) of the full
+    /// `args.width`x`args.height` frame, only the window's buffers are allocated
+    pub fn tile(args: Args, functs: Functs, x: i32, y: i32, w: i32, h: i32) -> Renderer {
+        let mut rend = Renderer {
+            args,
+            width: w,
+            height: h,
             functs,
-            image: Img8::new(args.width as u32, args.height as u32),
-            raw: vec![
-                vec![Color::new([0f64; 4], ColorType::SRgba); args.width as usize];
-                args.height as usize
-            ],
+            image: Img8::new(w as u32, h as u32),
+            raw: Vec::new(),
+            raw_sq: Vec::new(),
+            extra_samples: Vec::new(),
+            weights: Vec::new(),
+            interior: Vec::new(),
             rendered_samples: 0,
+            seeds: Vec::new(),
             not_rendering: true,
             texture: Img8::new(0, 0),
+            offset: (x, y),
+        };
+        rend.allocate();
+        rend
+    }
+
+    /// Sizes the accumulator to the renderer. The second moment and the extra
+    /// sample counts are only kept for `--noise`, and the weights only when
+    /// they can differ between pixels, otherwise every pixel has
+    /// `rendered_samples`
+    fn allocate(&mut self) {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let adaptive = self.args.noise > 0.0;
+        let zero = Color::new([0f64; 4], ColorType::SRgba);
+        self.raw = vec![vec![zero; w]; h];
+        self.raw_sq = match adaptive {
+            true => vec![vec![zero; w]; h],
+            false => Vec::new(),
+        };
+        self.extra_samples = match adaptive {
+            true => vec![vec![0; w]; h],
+            false => Vec::new(),
+        };
+        self.weights = match adaptive || self.splatting() {
+            true => vec![vec![0.0; w]; h],
+            false => Vec::new(),
+        };
+    }
+
+    /// Total filter weight of the samples in pixel (`x`, `y`) of this renderer
+    pub fn weight(&self, x: usize, y: usize) -> f64 {
+        match self.weights.get(y) {
+            Some(row) => row[x],
+            None => self.rendered_samples as f64,
         }
     }
 
@@ -404,127 +1100,570 @@ impl Renderer {
         self.width = w as i32;
         self.height = h as i32;
         self.image = Img8::new(w as u32, h as u32);
-        self.raw = vec![vec![Color::new([0f64; 4], ColorType::SRgba); w]; h];
+        self.allocate();
+        self.interior = Vec::new();
         self.rendered_samples = 0;
+        self.seeds = Vec::new();
+    }
+
+    /// Size of one pixel of the full frame on the complex plane
+    pub fn pixel_delta(&self) -> Cf64 {
+        normalize_coords(1, 1, self.args.width, self.args.height, self.args.zoom)
+            - normalize_coords(0, 0, self.args.width, self.args.height, self.args.zoom)
+    }
+
+    /// Frame coordinates of render index `i`
+    pub fn coords(&self, i: i32) -> (i32, i32) {
+        let (x, y) = self.local(i);
+        (x as i32 + self.offset.0, y as i32 + self.offset.1)
+    }
+
+    /// Position of render index `i` in this renderer's buffers
+    pub fn local(&self, i: i32) -> (usize, usize) {
+        block_local(i as usize, self.width as usize, self.height as usize)
+    }
+
+    /// How many samples pixel `i` has accumulated
+    pub fn samples_at(&self, i: i32) -> usize {
+        let (x, y) = self.local(i);
+        self.rendered_samples + self.extra_samples.get(y).map_or(0, |row| row[x])
+    }
+
+    /// Bits that only depend on `--seed` and the frame position (`x`, `y`)
+    pub fn pixel_hash(&self, x: i32, y: i32) -> u64 {
+        let index = y as u64 * self.args.width as u64 + x as u64;
+        sampler::hash(self.args.seed.unwrap_or(0) ^ sampler::hash(index))
+    }
+
+    /// Jitter source for frame pixel (`x`, `y`), with `--seed` it only depends
+    /// on the seed, the pixel's position and how many samples it already has
+    pub fn rng(&self, x: i32, y: i32, start: usize) -> Box<dyn RngCore> {
+        match self.args.seed {
+            Some(_) => Box::new(StdRng::seed_from_u64(
+                self.pixel_hash(x, y) ^ sampler::hash(start as u64),
+            )),
+            None => Box::new(rand::thread_rng()),
+        }
+    }
+
+    /// Where in frame pixel (`x`, `y`) its samples `start..start + samples`
+    /// go, in [0, 1)²
+    pub fn offsets(&self, x: i32, y: i32, start: usize, samples: usize) -> Vec<(f64, f64)> {
+        sampler::offsets(
+            self.args.pattern,
+            start,
+            samples,
+            self.pixel_hash(x, y),
+            &mut *self.rng(x, y, start),
+        )
     }
 
     pub fn pixel(&self, i: i32, samples: usize) -> Color {
-        let mut out = Color::new([0.0; 4], ColorType::SRgba);
-        let d: Cf64 = normalize_coords(1, 1, self.width, self.height, self.args.zoom)
-            - normalize_coords(0, 0, self.width, self.height, self.args.zoom);
-        let mut rng = rand::thread_rng();
-        for _ in 0..samples {
-            let mut c = normalize_coords(
-                i / self.height,
-                i % self.height,
-                self.width,
-                self.height,
-                self.args.zoom,
-            ) + self.args.origin;
-            c.re += d.re * (rng.gen_range(-1.0..1.0) / self.args.sampled);
-            c.im += d.im * (rng.gen_range(-1.0..1.0) / self.args.sampled);
-            c = (self.functs.cmap_funct)(c);
-            let mut z = (self.functs.init_funct)(self.args.z_init, c);
-            let mut i = 0.0;
-            let mut s = 0.0;
-            let mut der = Cf64::new(1.0, 0.0);
-            let mut tot_der = Cf64::new(1.0, 0.0);
-            let dc = Cf64::new(1.0, 0.0);
-            let mut test = z;
-            let mut old = z;
-            let chk = d.re.min(d.im) * 0.5;
-
-            let mut period = 1;
-            while (self.functs.conditional)(self, z, der, tot_der) && i < self.args.limit {
-                tot_der += der;
-                der = (der * 2.0 * z) + dc;
-                z = (self.functs.iter_funct)(z, c, (self.functs.cmap_funct)(self.args.julia));
-                i += 1.0;
-                s += (-(abs(z + 1.0))).exp();
-
-                let dif = z - old;
-                if dif.re.abs() < chk && dif.im.abs() < chk {
-                    i = self.args.limit;
-                    s = self.args.limit;
+        self.pixel_moments(i, samples).0
+    }
+
+    /// Sum and sum of squares of `samples` new contributions to pixel `i`
+    pub fn pixel_moments(&self, i: i32, samples: usize) -> (Color, Color) {
+        let d = self.pixel_delta();
+        let (x, y) = self.coords(i);
+        let offsets = self.offsets(x, y, self.samples_at(i), samples);
+        moments(self.contributions(x, y, d, &offsets).into_iter())
+    }
+
+    /// Shaded samples at `offsets` of frame pixel (`x`, `y`), or their rays
+    /// in `--march` mode
+    pub fn contributions(&self, x: i32, y: i32, d: Cf64, offsets: &[(f64, f64)]) -> Vec<Color> {
+        match &self.functs.march {
+            Some(march) => offsets
+                .iter()
+                .map(|o| self.march_sample(march, x, y, *o))
+                .collect(),
+            None => self
+                .sample_batch(x, y, d, offsets)
+                .iter()
+                .map(|e| self.shade(e))
+                .collect(),
+        }
+    }
+
+    pub fn pixel_samples(&self, i: i32, samples: usize) -> Vec<Sample> {
+        let d = self.pixel_delta();
+        let (x, y) = self.coords(i);
+        self.sample_batch(x, y, d, &self.offsets(x, y, self.samples_at(i), samples))
+    }
+
+    /// Contributions of frame pixel (`x`, `y`)'s samples `start..start + samples`
+    /// with where they landed, in frame pixels
+    pub fn pixel_splats(&self, x: i32, y: i32, start: usize, samples: usize) -> Vec<Splat> {
+        let d = self.pixel_delta();
+        let offsets = self.offsets(x, y, start, samples);
+        offsets
+            .iter()
+            .zip(self.contributions(x, y, d, &offsets))
+            .map(|(o, color)| (self.landing(x, y, *o), color))
+            .collect()
+    }
+
+    /// Where the sample at `offset` of frame pixel (`x`, `y`) lands, in frame
+    /// pixels
+    pub fn landing(&self, x: i32, y: i32, offset: (f64, f64)) -> (f64, f64) {
+        (
+            x as f64 + (offset.0 * 2.0 - 1.0) / self.args.sampled,
+            y as f64 + (offset.1 * 2.0 - 1.0) / self.args.sampled,
+        )
+    }
+
+    /// Whether samples are spread over neighbouring pixels by `--filter`, a
+    /// box of radius 0.5 keeps every sample in its own pixel
+    pub fn splatting(&self) -> bool {
+        !(self.args.filter == Filter::Box && self.args.filter_radius == 0.5)
+    }
+
+    /// Frame pixels outside this renderer whose samples can reach into it
+    pub fn apron(&self) -> Vec<(i32, i32)> {
+        if !self.splatting() {
+            return Vec::new();
+        }
+        let a = ((self.args.filter_radius + 1.0 / self.args.sampled).ceil() as i32 - 1).max(0);
+        let (x0, y0) = self.offset;
+        let mut apron = Vec::new();
+        for y in (y0 - a).max(0)..(y0 + self.height + a).min(self.args.height) {
+            for x in (x0 - a).max(0)..(x0 + self.width + a).min(self.args.width) {
+                if x < x0 || y < y0 || x >= x0 + self.width || y >= y0 + self.height {
+                    apron.push((x, y));
                 }
+            }
+        }
+        apron
+    }
 
-                period += 1;
-                if period > self.args.cycles {
-                    period = 0;
-                    old = z;
+    /// Adds samples to every pixel of this renderer their filter reaches
+    pub(crate) fn splat(&mut self, splats: &[Splat]) {
+        let radius = self.args.filter_radius;
+        let filter = self.args.filter;
+        for ((px, py), e) in splats.iter() {
+            // interior samples are still in `--set-color`'s mode, convert before
+            // weighting so the sRGB curve isn't applied to the weight too
+            let e = &e.to(ColorType::SRgba);
+            let (lx, ly) = (px - self.offset.0 as f64, py - self.offset.1 as f64);
+            let x_range = ((lx - radius).ceil() as i32).max(0)
+                ..=((lx + radius).floor() as i32).min(self.width - 1);
+            for y in ((ly - radius).ceil() as i32).max(0)
+                ..=((ly + radius).floor() as i32).min(self.height - 1)
+            {
+                for x in x_range.clone() {
+                    let w = filter.weight(lx - x as f64, ly - y as f64, radius);
+                    if w == 0.0 {
+                        continue;
+                    }
+                    let (x, y) = (x as usize, y as usize);
+                    self.raw[y][x] = self.raw[y][x] + (*e * w);
+                    if let Some(row) = self.raw_sq.get_mut(y) {
+                        row[x] = row[x] + (*e * *e * w);
+                    }
+                    self.weights[y][x] += w;
                 }
-                test += z;
             }
+        }
+    }
+
+    fn clear(&mut self) {
+        for row in self.raw.iter_mut().chain(self.raw_sq.iter_mut()) {
+            row.fill(Color::new([0f64; 4], ColorType::SRgba));
+        }
+        for row in self.extra_samples.iter_mut() {
+            row.fill(0);
+        }
+        for row in self.weights.iter_mut() {
+            row.fill(0.0);
+        }
+    }
+
+    /// Iterates the point at `offset` (in [0, 1)², spread by `--sampled`) of
+    /// frame pixel (`x`, `y`), `d` is the size of a pixel
+    pub fn sample(&self, x: i32, y: i32, d: Cf64, offset: (f64, f64)) -> Sample {
+        if let Some(period) = self.filled_period(x, y) {
+            return self.interior_sample(period);
+        }
+        let c = self.point(x, y, d, offset);
+        if !self.args.newton.is_empty() {
+            return self.newton_sample(c);
+        }
+        if let Some(sequence) = &self.args.lyapunov {
+            return self.lyapunov_sample(c, sequence);
+        }
+        if let Some(slice) = &self.functs.slice {
+            return self.slice_sample(slice, c);
+        }
+        if let Some(period) = (self.functs.interior_test)(c) {
+            return self.interior_sample(period);
+        }
+        let k = self.functs.added(&self.args, c);
+        let orbit = PlaneOrbit {
+            functs: &self.functs,
+            state: self
+                .functs
+                .start(self.args.z_init, c, k, self.args.is_julia),
+            k,
+        };
+        let inside = |z, der, tot_der| (self.functs.conditional)(self, z, der, tot_der);
+        iterate(orbit, self.args.limit, inside).0
+    }
 
-            let mut color = (self.functs.color_funct)(self, i, s, z, der);
+    /// The point on the complex plane `sample` iterates
+    pub fn point(&self, x: i32, y: i32, d: Cf64, offset: (f64, f64)) -> Cf64 {
+        let mut c = normalize_coords(x, y, self.args.width, self.args.height, self.args.zoom)
+            + self.args.origin;
+        c.re += d.re * ((offset.0 * 2.0 - 1.0) / self.args.sampled);
+        c.im += d.im * ((offset.1 * 2.0 - 1.0) / self.args.sampled);
+        (self.functs.cmap_funct)(c)
+    }
 
-            color = color.to_sRgba();
-            if i < self.args.limit {
-                out = out + (color * color);
-            } else {
-                out = out + (self.args.set_color * self.args.set_color);
+    /// `sample` for every offset, `LANES` at a time through the SIMD kernel
+    /// when the formula has one and `--scalar` isn't set
+    pub fn sample_batch(&self, x: i32, y: i32, d: Cf64, offsets: &[(f64, f64)]) -> Vec<Sample> {
+        let kernel = match self.functs.kernel {
+            Some(kernel) if !self.args.scalar && self.filled_period(x, y).is_none() => kernel,
+            _ => return offsets.iter().map(|o| self.sample(x, y, d, *o)).collect(),
+        };
+        let julia = (self.functs.cmap_funct)(self.args.julia);
+        let mut out = Vec::with_capacity(offsets.len());
+        for chunk in offsets.chunks(LANES) {
+            let mut c = [Cf64::new(0.0, 0.0); LANES];
+            let mut interior = [None; LANES];
+            for (l, o) in chunk.iter().enumerate() {
+                c[l] = self.point(x, y, d, *o);
+                interior[l] = (self.functs.interior_test)(c[l]);
+            }
+            let z = c.map(|c| self.functs.start(self.args.z_init, c, c, false).z);
+            let k = if kernel.julia { [julia; LANES] } else { c };
+            let live = std::array::from_fn(|l| l < chunk.len() && interior[l].is_none());
+            let lanes = simd::iterate(
+                kernel,
+                z,
+                k,
+                live,
+                self.args.limit,
+                self.functs.bail,
+                self.args.derbail,
+            );
+            for l in 0..chunk.len() {
+                out.push(match interior[l] {
+                    Some(period) => self.interior_sample(period),
+                    None => lanes[l],
+                });
             }
         }
         out
     }
-    #[allow(clippy::needless_late_init)]
+
+    /// Compares the SIMD kernel against `sample` on a grid of points over
+    /// the frame, returns how many of them differ. Everything has to match
+    /// bit for bit but `s`, which the kernel sums with its own `exp`
+    pub fn check_kernel(&self) -> usize {
+        let d = self.pixel_delta();
+        let offsets = [
+            (0.5, 0.5),
+            (0.1, 0.7),
+            (0.9, 0.3),
+            (0.25, 0.25),
+            (0.6, 0.95),
+        ];
+        (0..64 * 64)
+            .into_par_iter()
+            .map(|n| {
+                let x = (n % 64) * self.args.width / 64;
+                let y = (n / 64) * self.args.height / 64;
+                self.sample_batch(x, y, d, &offsets)
+                    .iter()
+                    .zip(offsets.iter())
+                    .filter(|(e, o)| {
+                        let f = self.sample(x, y, d, **o);
+                        let bits = |e: &Sample| {
+                            [e.i, e.z.re, e.z.im, e.der.re, e.der.im].map(f64::to_bits)
+                        };
+                        let close = (e.s - f.s).abs() <= 1e-12 * e.s.abs().max(f.s.abs());
+                        bits(e) != bits(&f) || !close || e.period != f.period
+                    })
+                    .count()
+            })
+            .sum()
+    }
+
+    /// What `sample` returns for a point known not to escape
+    fn interior_sample(&self, period: u32) -> Sample {
+        Sample {
+            i: self.args.limit,
+            s: self.args.limit,
+            z: Cf64::new(0.0, 0.0),
+            der: Cf64::new(1.0, 0.0),
+            period,
+        }
+    }
+
+    /// The `--color-mode` color of an escaping sample in sRGB, every palette
+    /// is moved along its own cycle by `--phase`. Density modes color their
+    /// pixels through it too
+    pub fn palette(&self, e: &Sample) -> Color {
+        (self.functs.color_funct)(self, e.i, e.s, e.z, e.der).to_sRgba()
+    }
+
+    /// Turns one sample into its contribution to the accumulator, this is the
+    /// only part of a render that depends on the palette
+    pub fn shade(&self, e: &Sample) -> Color {
+        if e.i < self.args.limit {
+            let color = self.palette(e);
+            color * color
+        } else if self.args.period_color && e.period > 0 {
+            // golden angle steps keep neighbouring periods apart
+            let hue = (e.period as f64 * 137.507_764).rem_euclid(360.0);
+            let color = Color::from_hsv(hue, 0.6, 0.8, 1.0).to_sRgba();
+            color * color
+        } else {
+            self.args.set_color * self.args.set_color
+        }
+    }
+
     pub fn render_samples(&mut self, samples: usize, progress: bool) {
+        self.resume_samples(samples, 0, progress);
+    }
+
+    /// Renders a pass of `samples` per pixel starting at render index `start`,
+    /// with `--checkpoint` the pass is split up and saved every `--checkpoint-secs`.
+    /// When splatting, the `apron` pixels are rendered after this renderer's own
+    pub fn resume_samples(&mut self, samples: usize, start: usize, progress: bool) {
+        let now = SystemTime::now();
+        self.not_rendering = false;
+        self.trace_interior();
+        let apron = self.apron();
+        let own = (self.width * self.height) as usize;
+        let len = own + apron.len();
+        if self.splatting() && self.rendered_samples == 0 && start == 0 {
+            self.clear();
+        }
+        // with a checkpoint, a row of blocks at a time
+        let chunk = match self.args.checkpoint {
+            Some(_) => self.width as usize * BLOCK,
+            None => len,
+        }
+        .max(1);
+        with_progress(len, start, progress, |counter| {
+            let mut saved = Instant::now();
+            let mut done = start;
+            while done < len {
+                let end = (done + chunk).min(len);
+                if self.splatting() {
+                    let splats: Vec<Vec<Splat>> = par_blocks(done..end, counter, |i| {
+                        if i < own {
+                            let (x, y) = self.coords(i as i32);
+                            self.pixel_splats(x, y, self.samples_at(i as i32), samples)
+                        } else {
+                            let (x, y) = apron[i - own];
+                            self.pixel_splats(x, y, self.rendered_samples, samples)
+                        }
+                    });
+                    for e in splats.iter() {
+                        self.splat(e);
+                    }
+                } else {
+                    let out: Vec<(Color, Color)> = par_blocks(done..end, counter, |i| {
+                        self.pixel_moments(i as i32, samples)
+                    });
+                    self.accumulate(done, &out, samples);
+                }
+                done = end;
+                if let Some(path) = &self.args.checkpoint {
+                    if done < len && saved.elapsed().as_secs() >= self.args.checkpoint_secs {
+                        if let Err(e) = self.save_checkpoint(path, samples, done) {
+                            eprintln!("Couldn't save checkpoint {}: {}", path, e);
+                        }
+                        saved = Instant::now();
+                    }
+                }
+            }
+        });
+        if progress {
+            println!("{:4.4}", now.elapsed().unwrap().as_secs_f32());
+        }
+        self.rendered_samples += samples;
+        self.note_seed();
+        if let Some(path) = &self.args.checkpoint {
+            if let Err(e) = self.save_checkpoint(path, 0, 0) {
+                eprintln!("Couldn't save checkpoint {}: {}", path, e);
+            }
+        }
+        self.not_rendering = true;
+    }
+
+    /// Same as `render_samples`, but keeps every sample's iteration data so the
+    /// image can be recolored later without iterating again
+    pub fn record_samples(&mut self, samples: usize, progress: bool) -> IterData {
         let now = SystemTime::now();
         self.not_rendering = false;
-        let out: Vec<Color>;
+        self.trace_interior();
+        let len = (self.width * self.height) as usize;
+        let data: Vec<Sample> = with_progress(len, 0, progress, |counter| {
+            par_blocks(0..len, counter, |i| self.pixel_samples(i as i32, samples))
+                .into_iter()
+                .flatten()
+                .collect()
+        });
+        let data = IterData::new(self.width, self.height, samples, data);
+        let out = self.shade_data(&data);
+        self.accumulate(0, &out, samples);
         if progress {
-            let progress = Mutex::new(Progress::new());
-            let bar: Bar = progress
-                .lock()
-                .unwrap()
-                .bar((self.width * self.height) as usize, "");
-            out = (0..(self.width * self.height))
+            println!("{:4.4}", now.elapsed().unwrap().as_secs_f32());
+        }
+        self.rendered_samples += samples;
+        self.note_seed();
+        self.not_rendering = true;
+        data
+    }
+
+    /// Rebuilds the accumulator from stored iteration data, only `color_funct`
+    /// runs so this is cheap enough to do once per animation frame
+    pub fn recolor(&mut self, data: &IterData) {
+        assert!(
+            data.width == self.width && data.height == self.height,
+            "iteration data is {}x{}, renderer is {}x{}",
+            data.width,
+            data.height,
+            self.width,
+            self.height
+        );
+        let out = self.shade_data(data);
+        self.rendered_samples = 0;
+        self.accumulate(0, &out, data.samples);
+        self.rendered_samples = data.samples;
+    }
+
+    fn shade_data(&self, data: &IterData) -> Vec<(Color, Color)> {
+        data.data
+            .par_chunks(data.samples)
+            .map(|px| moments(px.iter().map(|e| self.shade(e))))
+            .collect()
+    }
+
+    fn accumulate(&mut self, start: usize, out: &[(Color, Color)], samples: usize) {
+        // the first pass overwrites whatever a resize left behind
+        let fresh = self.rendered_samples == 0;
+        let zero = Color::new([0f64; 4], ColorType::SRgba);
+        for (i, (e, sq)) in out.iter().enumerate() {
+            let (x, y) = self.local((start + i) as i32);
+            self.raw[y][x] = if fresh { zero } else { self.raw[y][x] } + *e;
+            if let Some(row) = self.raw_sq.get_mut(y) {
+                row[x] = if fresh { zero } else { row[x] } + *sq;
+            }
+            if let Some(row) = self.extra_samples.get_mut(y) {
+                row[x] = if fresh { 0 } else { row[x] };
+            }
+            if let Some(row) = self.weights.get_mut(y) {
+                row[x] = if fresh { 0.0 } else { row[x] } + samples as f64;
+            }
+        }
+    }
+
+    /// Standard error of pixel `i`'s mean contribution, the largest of its
+    /// color channels
+    pub fn noise(&self, i: i32) -> f64 {
+        let (x, y) = self.local(i);
+        let (n, w) = (self.samples_at(i) as f64, self.weight(x, y));
+        if n < 2.0 || w <= 0.0 {
+            return f64::INFINITY;
+        }
+        let mean = self.raw[y][x] / w;
+        let var = (self.raw_sq[y][x] / w) - (mean * mean);
+        var.ch[..3]
+            .iter()
+            .map(|v| (v.max(0.0) / n).sqrt())
+            .fold(0.0, f64::max)
+    }
+
+    /// Keeps adding `step` samples to every pixel whose `noise` is above
+    /// `threshold` until it's below or the pixel has `max_samples`
+    pub fn render_adaptive(&mut self, step: usize, threshold: f64, max_samples: usize) {
+        let now = SystemTime::now();
+        self.not_rendering = false;
+        let step = step.max(1);
+        loop {
+            let noisy: Vec<i32> = (0..(self.width * self.height))
                 .into_par_iter()
+                .filter(|i| self.samples_at(*i) < max_samples && self.noise(*i) > threshold)
+                .collect();
+            if noisy.is_empty() {
+                break;
+            }
+            println!("refining {} pixels", noisy.len());
+            if self.splatting() {
+                let splats: Vec<Vec<Splat>> = noisy
+                    .par_iter()
+                    .map(|i| {
+                        let samples = step.min(max_samples - self.samples_at(*i));
+                        let (x, y) = self.coords(*i);
+                        self.pixel_splats(x, y, self.samples_at(*i), samples)
+                    })
+                    .collect();
+                for (i, e) in noisy.iter().zip(splats) {
+                    let (x, y) = self.local(*i);
+                    self.extra_samples[y][x] += e.len();
+                    self.splat(&e);
+                }
+                continue;
+            }
+            let out: Vec<(Color, Color)> = noisy
+                .par_iter()
                 .map(|i| {
-                    progress.lock().unwrap().inc_and_draw(&bar, 1);
-                    Renderer::pixel(self, i as i32, samples)
+                    let samples = step.min(max_samples - self.samples_at(*i));
+                    self.pixel_moments(*i, samples)
                 })
                 .collect();
-        } else {
-            out = (0..(self.width * self.height))
-                .into_par_iter()
-                .map(|i| Renderer::pixel(self, i as i32, samples))
-                .collect();
-        }
-
-        for (i, e) in out.iter().enumerate() {
-            let (x, y) = (
-                (i as i32 / (self.height)) as u32,
-                (i as i32 % (self.height)) as u32,
-            );
-            if (y as i32) < self.height {
-                if self.rendered_samples > 0 {
-                    self.raw[y as usize][x as usize] = self.raw[y as usize][x as usize] + *e;
-                } else {
-                    self.raw[y as usize][x as usize] = *e;
-                }
+            for (i, (e, sq)) in noisy.iter().zip(out) {
+                let samples = step.min(max_samples - self.samples_at(*i));
+                let (x, y) = self.local(*i);
+                self.raw[y][x] = self.raw[y][x] + e;
+                self.raw_sq[y][x] = self.raw_sq[y][x] + sq;
+                self.extra_samples[y][x] += samples;
+                self.weights[y][x] += samples as f64;
             }
         }
         println!("{:4.4}", now.elapsed().unwrap().as_secs_f32());
-        self.rendered_samples += samples;
         self.not_rendering = true;
     }
 
+    /// Samples per pixel as an image, blue for `rendered_samples` up to red
+    /// for `max_samples`
+    pub fn sample_heatmap(&self, max_samples: usize) -> Img8 {
+        let mut heatmap = Img8::new(self.width as u32, self.height as u32);
+        let range = (max_samples.saturating_sub(self.rendered_samples)).max(1) as f64;
+        for i in 0..(self.width * self.height) {
+            let (x, y) = self.local(i);
+            let extra = self.extra_samples.get(y).map_or(0, |row| row[x]);
+            let t = (extra as f64 / range).min(1.0);
+            let color = Color::new([240.0 * (1.0 - t), 1.0, 1.0, 1.0], ColorType::Hsva).to_Rgba();
+            heatmap.put_pixel(x as u32, y as u32, Rgba::from(color.to_arr8()));
+        }
+        heatmap
+    }
+
     pub fn process_image(&mut self) {
         for i in 0..(self.width * self.height) {
-            let (x, y) = (
-                (i as i32 / (self.height)) as u32,
-                (i as i32 % (self.height)) as u32,
-            );
+            let (x, y) = ((i % self.width) as u32, (i / self.width) as u32);
             if (y as i32) < self.height {
-                let e = self.raw[y as usize][x as usize] / self.rendered_samples as f64;
+                // negative filter lobes can leave a pixel with no weight or
+                // below zero, those stay black instead of turning NaN
+                let w = self.weight(x as usize, y as usize);
+                let e = if w > 0.0 {
+                    self.raw[y as usize][x as usize] / w
+                } else {
+                    Color::new([0f64; 4], ColorType::SRgba)
+                };
                 self.image.put_pixel(
                     x,
                     y,
                     Rgba::from(
                         e.to_Rgba()
                             .to_arr()
-                            .map(|v| (v.sqrt() * u8::MAX as f64) as u8),
+                            .map(|v| (v.max(0.0).sqrt() * u8::MAX as f64) as u8),
                     ),
                 );
             }
@@ -535,9 +1674,215 @@ impl Renderer {
         self.args = args.clone();
         self.width = args.width;
         self.height = args.height;
+        self.interior = Vec::new();
     }
 
     pub fn update_functs(&mut self, functs: Functs) {
         self.functs = functs;
     }
 }
+
+/// Side of the square of pixels one render task works through
+const BLOCK: usize = 16;
+
+/// Position of render index `i` in a `w`x`h` buffer. Render order goes
+/// through `BLOCK`x`BLOCK` blocks row by row, and row by row inside each
+/// block, so neighbouring indices are neighbouring pixels. Blocks on the
+/// right and bottom edges are cut short
+pub(crate) fn block_local(i: usize, w: usize, h: usize) -> (usize, usize) {
+    let by = i / (BLOCK * w);
+    let bh = BLOCK.min(h - by * BLOCK);
+    let i = i - by * BLOCK * w;
+    let bx = i / (BLOCK * bh);
+    let bw = BLOCK.min(w - bx * BLOCK);
+    let i = i - bx * BLOCK * bh;
+    (bx * BLOCK + i % bw, by * BLOCK + i / bw)
+}
+
+/// `f` over render indices `range` in parallel, in runs of a block's worth
+/// of indices, bumping `counter` once per run. Results are in render order
+fn par_blocks<T: Send>(
+    range: Range<usize>,
+    counter: &AtomicUsize,
+    f: impl Fn(usize) -> T + Sync,
+) -> Vec<T> {
+    let end = range.end;
+    let starts: Vec<usize> = range.step_by(BLOCK * BLOCK).collect();
+    starts
+        .into_par_iter()
+        .flat_map_iter(|s| {
+            let out: Vec<T> = (s..(s + BLOCK * BLOCK).min(end)).map(&f).collect();
+            counter.fetch_add(out.len(), Ordering::Relaxed);
+            out
+        })
+        .collect()
+}
+
+/// Where a sample landed (in frame pixels) and its contribution
+pub type Splat = ((f64, f64), Color);
+
+/// Sum and sum of squares of a pixel's sample contributions
+fn moments(contributions: impl Iterator<Item = Color>) -> (Color, Color) {
+    let zero = Color::new([0.0; 4], ColorType::SRgba);
+    contributions.fold((zero, zero), |(sum, sq), e| (sum + e, sq + (e * e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small renderer for `args` with the functions it selects
+    fn renderer(mut args: Args) -> Renderer {
+        args.width = args.width.min(64);
+        args.height = args.height.min(48);
+        let functs = Functs::from_args(&args);
+        Renderer::new(args, functs)
+    }
+
+    fn escaped(s: f64) -> Sample {
+        Sample {
+            i: 10.0,
+            s,
+            z: Cf64::new(3.0, 4.0),
+            der: Cf64::new(1.0, 2.0),
+            period: 0,
+        }
+    }
+
+    /// Period `sample` finds at `c`, found by cycle detection alone
+    fn period_at(args: Args, c: Cf64) -> u32 {
+        let mut rend = renderer(Args {
+            origin: c,
+            limit: 4096.0,
+            ..args
+        });
+        rend.functs.interior_test = Functions::no_interior_test;
+        let (x, y) = (rend.args.width / 2, rend.args.height / 2);
+        rend.sample(x, y, rend.pixel_delta(), (0.5, 0.5)).period
+    }
+
+    #[test]
+    fn cycles_of_bulb_centers_are_found() {
+        assert_eq!(period_at(Args::new(), Cf64::new(-1.0, 0.0)), 2);
+        let rabbit = Cf64::new(-0.122_561_166_876_65, 0.744_861_766_619_74);
+        assert_eq!(period_at(Args::new(), rabbit), 3);
+    }
+
+    #[test]
+    fn cycles_finish_the_hybrid() {
+        // z repeats every 2 steps but the sequence only every 3
+        let args = Args {
+            hybrid: Some("mandelbrot*3".parse().unwrap()),
+            ..Args::new()
+        };
+        assert_eq!(period_at(args, Cf64::new(-1.0, 0.0)), 6);
+    }
+
+    #[test]
+    fn render_order_visits_every_pixel_once() {
+        // edge blocks are cut short on both sides
+        let (w, h) = (37, 21);
+        let mut seen = vec![vec![false; w]; h];
+        for i in 0..w * h {
+            let (x, y) = block_local(i, w, h);
+            assert!(!seen[y][x], "{} {}", x, y);
+            seen[y][x] = true;
+        }
+        assert_eq!(block_local(BLOCK, w, h), (0, 1));
+    }
+
+    #[test]
+    fn blocks_keep_render_order_and_count_progress() {
+        let counter = AtomicUsize::new(0);
+        let out = par_blocks(5..1000, &counter, |i| i * 2);
+        assert_eq!(out, (5..1000).map(|i| i * 2).collect::<Vec<usize>>());
+        assert_eq!(counter.load(Ordering::Relaxed), 995);
+    }
+
+    #[test]
+    fn kernel_matches_the_scalar_path() {
+        for fractal_mode in [Formula::Mandelbrot, Formula::BurningShip] {
+            for is_julia in [false, true] {
+                for bail_mode in [0, 2] {
+                    let rend = renderer(Args {
+                        fractal_mode,
+                        is_julia,
+                        julia: Cf64::new(-0.8, 0.156),
+                        bail_mode,
+                        limit: 128.0,
+                        ..Args::new()
+                    });
+                    assert!(rend.functs.kernel.is_some());
+                    assert_eq!(
+                        rend.check_kernel(),
+                        0,
+                        "{} {} {}",
+                        fractal_mode,
+                        is_julia,
+                        bail_mode
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn phase_moves_along_the_palette() {
+        // miles_coloring2's hue cycle is 2 pi of sqrt(s)
+        let mut args = Args::new();
+        args.color_mode = 3;
+        args.phase = 90.0;
+        let shifted = renderer(args.clone()).palette(&escaped(4.0));
+        args.phase = 0.0;
+        let moved = (2.0 + 90f64.to_radians()).powi(2);
+        let moved = renderer(args).palette(&escaped(moved));
+        for (a, b) in shifted.ch.iter().zip(moved.ch.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn phase_of_a_whole_cycle_is_no_phase() {
+        for color_mode in 0..4 {
+            let mut args = Args::new();
+            args.color_mode = color_mode;
+            let plain = renderer(args.clone()).palette(&escaped(7.5));
+            args.phase = 360.0;
+            let turned = renderer(args).palette(&escaped(7.5));
+            for (a, b) in plain.ch.iter().zip(turned.ch.iter()) {
+                assert!((a - b).abs() < 1e-9, "color mode {}", color_mode);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_counts_are_continuous() {
+        // a step past the escape z^d in place of z, the count shouldn't move
+        for (mode, d) in [(Formula::Mandelbrot, 2), (Formula::Multibrot, 3)] {
+            let rend = renderer(Args {
+                fractal_mode: mode,
+                ..Args::new()
+            });
+            let z = Cf64::new(9.0, -5.0);
+            let before = Functions::smooth_count(&rend, 10.0, z);
+            let after = Functions::smooth_count(&rend, 11.0, z.powi(d));
+            assert!(
+                (before - after).abs() < 1e-9,
+                "{}: {} {}",
+                mode,
+                before,
+                after
+            );
+            assert!(before > 9.0 && before < 11.0, "{}", before);
+        }
+        // no degree, no fraction
+        let rend = renderer(Args {
+            fractal_mode: Formula::Sine,
+            ..Args::new()
+        });
+        assert_eq!(
+            Functions::smooth_count(&rend, 10.0, Cf64::new(60.0, 0.0)),
+            10.0
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// fracgen
+// Sample patterns
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use rand::{Rng, RngCore};
+use std::{fmt, str::FromStr, sync::RwLock};
+
+/// Where the samples of a pixel land inside it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+    R2,
+    BlueNoise,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "random" => Ok(Pattern::Random),
+            "stratified" => Ok(Pattern::Stratified),
+            "halton" => Ok(Pattern::Halton),
+            "sobol" => Ok(Pattern::Sobol),
+            "r2" => Ok(Pattern::R2),
+            "blue" => Ok(Pattern::BlueNoise),
+            _ => Err(format!(
+                "unknown pattern {}, expected random, stratified, halton, sobol, r2 or blue",
+                string
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Pattern::Random => "random",
+            Pattern::Stratified => "stratified",
+            Pattern::Halton => "halton",
+            Pattern::Sobol => "sobol",
+            Pattern::R2 => "r2",
+            Pattern::BlueNoise => "blue",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// splitmix64, turns a pixel/seed pair into well mixed bits
+pub fn hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn radical_inverse(mut k: u64, base: u64) -> f64 {
+    let inv = 1.0 / base as f64;
+    let (mut f, mut out) = (inv, 0.0);
+    while k > 0 {
+        out += (k % base) as f64 * f;
+        k /= base;
+        f *= inv;
+    }
+    out
+}
+
+fn sobol2(k: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut v = 1u32 << 31;
+    let mut k = k;
+    let mut bit = 0;
+    while k > 0 {
+        if k & 1 == 1 {
+            x ^= 1 << (31 - bit);
+            y ^= v;
+        }
+        k >>= 1;
+        v ^= v >> 1;
+        bit += 1;
+    }
+    (x, y)
+}
+
+fn torus_dist(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let dx = (a.0 - b.0).abs();
+    let dy = (a.1 - b.1).abs();
+    let (dx, dy) = (dx.min(1.0 - dx), dy.min(1.0 - dy));
+    dx * dx + dy * dy
+}
+
+/// Mitchell's best candidate points on the unit torus, shared by every pixel
+/// and grown as passes ask for more, so each point is only placed once
+struct BlueNoise {
+    state: u64,
+    points: Vec<(f64, f64)>,
+}
+
+static BLUE_NOISE: RwLock<BlueNoise> = RwLock::new(BlueNoise {
+    state: 0,
+    points: Vec::new(),
+});
+
+impl BlueNoise {
+    fn grow(&mut self, len: usize) {
+        while self.points.len() < len {
+            let candidates = (self.points.len() + 1).min(16);
+            let (mut best, mut best_d) = ((0.0, 0.0), -1.0);
+            for _ in 0..candidates {
+                self.state = hash(self.state);
+                let u = unit(self.state);
+                self.state = hash(self.state);
+                let p = (u, unit(self.state));
+                let d = self
+                    .points
+                    .iter()
+                    .map(|q| torus_dist(p, *q))
+                    .fold(f64::INFINITY, f64::min);
+                if d > best_d {
+                    best = p;
+                    best_d = d;
+                }
+            }
+            self.points.push(best);
+        }
+    }
+}
+
+/// Points `start..start + count` of the shared blue noise set
+fn blue_noise(start: usize, count: usize) -> Vec<(f64, f64)> {
+    {
+        let set = BLUE_NOISE.read().unwrap();
+        if set.points.len() >= start + count {
+            return set.points[start..start + count].to_vec();
+        }
+    }
+    let mut set = BLUE_NOISE.write().unwrap();
+    set.grow(start + count);
+    set.points[start..start + count].to_vec()
+}
+
+/// Offsets in [0, 1)² of samples `start..start + count` of one pixel.
+/// `scramble` decorrelates neighbouring pixels, it has to stay the same for a
+/// pixel across passes so progressive passes keep extending one sequence
+pub fn offsets(
+    pattern: Pattern,
+    start: usize,
+    count: usize,
+    scramble: u64,
+    rng: &mut dyn RngCore,
+) -> Vec<(f64, f64)> {
+    let rotate = |(u, v): (f64, f64)| {
+        (
+            (u + unit(scramble)).fract(),
+            (v + unit(hash(scramble))).fract(),
+        )
+    };
+    match pattern {
+        Pattern::Random => (0..count)
+            .map(|_| (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))
+            .collect(),
+        Pattern::Stratified => {
+            // strata are laid out per pass, each pass covers the whole pixel
+            let cols = (count as f64).sqrt().ceil().max(1.0) as usize;
+            let rows = count.div_ceil(cols).max(1);
+            (0..count)
+                .map(|j| {
+                    (
+                        ((j % cols) as f64 + rng.gen_range(0.0..1.0)) / cols as f64,
+                        ((j / cols) as f64 + rng.gen_range(0.0..1.0)) / rows as f64,
+                    )
+                })
+                .collect()
+        }
+        Pattern::Halton => (start..start + count)
+            .map(|k| {
+                rotate((
+                    radical_inverse(k as u64 + 1, 2),
+                    radical_inverse(k as u64 + 1, 3),
+                ))
+            })
+            .collect(),
+        Pattern::Sobol => (start..start + count)
+            .map(|k| {
+                let (x, y) = sobol2(k as u32);
+                // random digit scrambling keeps the (0, 2)-sequence structure
+                let (x, y) = (x ^ scramble as u32, y ^ (scramble >> 32) as u32);
+                (x as f64 / 4294967296.0, y as f64 / 4294967296.0)
+            })
+            .collect(),
+        Pattern::R2 => {
+            let g = 1.324_717_957_244_746;
+            let (a1, a2) = (1.0 / g, 1.0 / (g * g));
+            (start..start + count)
+                .map(|k| rotate(((0.5 + a1 * k as f64).fract(), (0.5 + a2 * k as f64).fract())))
+                .collect()
+        }
+        // a toroidal shift keeps the distances between the points, so every
+        // pixel gets its own copy of the one set
+        Pattern::BlueNoise => blue_noise(start, count).into_iter().map(rotate).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const PATTERNS: [Pattern; 6] = [
+        Pattern::Random,
+        Pattern::Stratified,
+        Pattern::Halton,
+        Pattern::Sobol,
+        Pattern::R2,
+        Pattern::BlueNoise,
+    ];
+
+    #[test]
+    fn offsets_stay_in_the_pixel() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for pattern in PATTERNS {
+            for scramble in [0, 7, u64::MAX] {
+                for (u, v) in offsets(pattern, 3, 37, scramble, &mut rng) {
+                    assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn passes_continue_one_sequence() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for pattern in [
+            Pattern::Halton,
+            Pattern::Sobol,
+            Pattern::R2,
+            Pattern::BlueNoise,
+        ] {
+            let whole = offsets(pattern, 0, 24, 42, &mut rng);
+            let mut passes = offsets(pattern, 0, 8, 42, &mut rng);
+            passes.extend(offsets(pattern, 8, 16, 42, &mut rng));
+            assert_eq!(whole, passes, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn blue_noise_keeps_its_points_apart() {
+        // best candidate spreads 64 points much further than chance would
+        let points = blue_noise(0, 64);
+        let closest = points
+            .iter()
+            .enumerate()
+            .flat_map(|(i, p)| points[i + 1..].iter().map(move |q| torus_dist(*p, *q)))
+            .fold(f64::INFINITY, f64::min);
+        assert!(closest.sqrt() > 0.04, "{}", closest.sqrt());
+    }
+}
@@ -0,0 +1,223 @@
+// fracgen
+// Scene files
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::Parser;
+
+use crate::renderer::Args;
+
+// A scene is one `key = value` line per render setting, keys are the long
+// argument names with underscores, so every scene line is also a valid flag
+
+impl Args {
+    /// The settings that decide what a render looks like, as scene text
+    pub fn to_scene(&self) -> String {
+        let c = self.set_color.ch.map(|v| (v * 255.0).round());
+        let mut lines = vec![
+            format!("width = {}", self.width),
+            format!("height = {}", self.height),
+            format!("name = {}", self.name),
+            format!("origin = {}", self.origin),
+            format!("z_init = {}", self.z_init),
+            format!("julia = {}", self.julia),
+            format!("zoom = {}", self.zoom),
+            format!("samples = {}", self.samples),
+            format!("sampled = {}", self.sampled),
+            format!("limit = {}", self.limit),
+            format!("derbail = {}", self.derbail),
+            format!("cexp = {}", self.cexp),
+            format!("set_color = {},{},{},{}", c[0], c[1], c[2], c[3]),
+            format!("is_julia = {}", self.is_julia),
+            format!("fractal_mode = {}", self.fractal_mode),
+            format!("color_mode = {}", self.color_mode),
+            format!("bail_mode = {}", self.bail_mode),
+            format!("phase = {}", self.phase),
+            format!("send_iters = {}", self.send_iters),
+            format!("noise = {}", self.noise),
+            format!("max_samples = {}", self.max_samples),
+            format!("adaptive_step = {}", self.adaptive_step),
+            format!("pattern = {}", self.pattern),
+            format!("filter = {}", self.filter),
+            format!("filter_radius = {}", self.filter_radius),
+            format!("mariani = {}", self.mariani),
+            format!("period_color = {}", self.period_color),
+            format!("buddha = {}", self.buddha),
+            format!("anti = {}", self.anti),
+            format!("metropolis = {}", self.metropolis),
+            format!("nova = {}", self.nova),
+            format!("relax = {}", self.relax),
+            format!("tolerance = {}", self.tolerance),
+        ];
+        if !self.nebula.is_empty() {
+            let limits: Vec<String> = self.nebula.iter().map(f64::to_string).collect();
+            lines.push(format!("nebula = {}", limits.join(",")));
+        }
+        if let Some(tone) = self.tone {
+            lines.push(format!("tone = {}", tone));
+        }
+        if let Some(hybrid) = &self.hybrid {
+            lines.push(format!("hybrid = {}", hybrid));
+        }
+        if let Some(bail) = self.bail {
+            lines.push(format!("bail = {}", bail));
+        }
+        if !self.params.is_empty() {
+            let params: Vec<String> = self.params.iter().map(f64::to_string).collect();
+            lines.push(format!("params = {}", params.join(",")));
+        }
+        if let Some(attractor) = self.attractor {
+            lines.push(format!("attractor = {}", attractor));
+            lines.push(format!("age = {}", self.age));
+        }
+        if let Some(path) = &self.flame {
+            lines.push(format!("flame = {}", path));
+        }
+        let list = |v: &[f64]| {
+            v.iter()
+                .map(f64::to_string)
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        if let Some(hyper) = self.hyper {
+            lines.push(format!("hyper = {}", hyper));
+            for (key, values) in [
+                ("slice", &self.slice),
+                ("rotate", &self.rotate),
+                ("hyper_julia", &self.hyper_julia),
+            ] {
+                if !values.is_empty() {
+                    lines.push(format!("{} = {}", key, list(values)));
+                }
+            }
+        }
+        if let Some(shape) = self.march {
+            lines.push(format!("march = {}", shape));
+            lines.push(format!("fov = {}", self.fov));
+            lines.push(format!("shadow = {}", self.shadow));
+            lines.push(format!("ao = {}", self.ao));
+            lines.push(format!("fog = {}", self.fog));
+            lines.push(format!("glow = {}", self.glow));
+            lines.push(format!("depth = {}", self.depth));
+            lines.push(format!("normals = {}", self.normals));
+            for (key, values) in [
+                ("camera", &self.camera),
+                ("look_at", &self.look_at),
+                ("light", &self.light),
+            ] {
+                if !values.is_empty() {
+                    lines.push(format!("{} = {}", key, list(values)));
+                }
+            }
+            if let Some(iters) = self.de_iters {
+                lines.push(format!("de_iters = {}", iters));
+            }
+        }
+        if let Some(sequence) = &self.lyapunov {
+            lines.push(format!("lyapunov = {}", sequence));
+        }
+        if !self.newton.is_empty() {
+            let coeffs: Vec<String> = self.newton.iter().map(|a| a.to_string()).collect();
+            lines.push(format!("newton = {}", coeffs.join(",")));
+        }
+        if let Some(seed) = self.seed {
+            lines.push(format!("seed = {}", seed));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Parses scene text, anything the scene leaves out keeps its default
+    pub fn from_scene(scene: &str) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once(String::from("fracgen")).chain(scene_argv(scene)))
+    }
+}
+
+/// Turns scene lines into command line arguments, blank lines and lines
+/// starting with `#` are skipped
+pub fn scene_argv(scene: &str) -> Vec<String> {
+    let mut argv = Vec::new();
+    for line in scene.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (line, "true"),
+        };
+        let flag = match key {
+            "is_julia" => String::from("-i"),
+            _ => format!("--{}", key.replace('_', "-")),
+        };
+        match value {
+            "true" => argv.push(flag),
+            "false" => {}
+            _ => argv.push(format!("{}={}", flag, value)),
+        }
+    }
+    argv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+
+    #[test]
+    fn scenes_round_trip() {
+        let args = Args::from_scene(
+            "width = 320\n\
+             origin = -0.75+0.1i\n\
+             julia = 0.285-0.01i\n\
+             is_julia = true\n\
+             zoom = 12.5\n\
+             hybrid = mandelbrot*2,burningship\n\
+             bail = 64\n\
+             params = 0.5,-1\n\
+             set_color = 10,20,30,255\n\
+             pattern = sobol\n\
+             filter = gaussian\n\
+             seed = 9\n",
+        )
+        .unwrap();
+        let again = Args::from_scene(&args.to_scene()).unwrap();
+        assert_eq!(again.to_scene(), args.to_scene());
+        assert_eq!(again.origin, Complex::new(-0.75, 0.1));
+        assert_eq!(again.julia, Complex::new(0.285, -0.01));
+        assert!(again.is_julia);
+        assert_eq!(
+            again.hybrid.unwrap().to_string(),
+            "mandelbrot*2,burningship"
+        );
+        assert_eq!((again.bail, again.seed), (Some(64.0), Some(9)));
+    }
+
+    #[test]
+    fn defaults_round_trip() {
+        let args = Args::new();
+        assert_eq!(
+            Args::from_scene(&args.to_scene()).unwrap().to_scene(),
+            args.to_scene()
+        );
+    }
+
+    #[test]
+    fn scene_lines_become_flags() {
+        let argv = scene_argv(
+            "# a comment\n\nis_julia = true\nmariani = false\nfractal_mode = tricorn\nanti\n",
+        );
+        assert_eq!(argv, ["-i", "--fractal-mode=tricorn", "--anti"]);
+        assert!(Args::from_scene("no_such_setting = 1").is_err());
+    }
+}
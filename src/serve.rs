@@ -0,0 +1,195 @@
+// fracgen
+// Tile server
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Slippy map tiles over the fractal plane: zoom level 0 is one tile showing
+// the scene's view (its origin and zoom), every level splits each tile in
+// four. Tile x grows to the right and y downwards like the renderer's pixels
+
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use image::ImageOutputFormat;
+use lru::LruCache;
+use num::complex::Complex;
+
+use crate::{
+    renderer::{Args, Functs},
+    tiled::render_rect,
+};
+
+type Cf64 = Complex<f64>;
+
+/// Tile side in pixels, what slippy map viewers expect
+pub const TILE: i32 = 256;
+/// Past this level tiles are smaller than f64 can tell apart
+const MAX_LEVEL: u32 = 46;
+
+const VIEWER: &str = include_str!("viewer.html");
+
+type Cache = Mutex<LruCache<(u32, u32, u32), Arc<Vec<u8>>>>;
+
+/// Settings for tile (`x`, `y`) of level `z`
+pub fn tile_args(args: &Args, z: u32, x: u32, y: u32) -> Args {
+    let n = (1u64 << z) as f64;
+    // center of the tile in [-1, 1]² of the level 0 view
+    let (u, v) = (
+        (x as f64 + 0.5) / n * 2.0 - 1.0,
+        (y as f64 + 0.5) / n * 2.0 - 1.0,
+    );
+    Args {
+        width: TILE,
+        height: TILE,
+        origin: args.origin + Cf64::new(u / args.zoom, v / args.zoom),
+        zoom: args.zoom * n,
+        checkpoint: None,
+        accum: None,
+        tile: 0,
+        ..args.clone()
+    }
+}
+
+/// Renders tile (`x`, `y`) of level `z` to png bytes
+pub fn render_png(args: &Args, z: u32, x: u32, y: u32) -> io::Result<Vec<u8>> {
+    let args = tile_args(args, z, x, y);
+    let rend = render_rect(&args, &Functs::from_args(&args), 0, 0, TILE, TILE);
+    let mut png = Cursor::new(Vec::new());
+    rend.image
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(png.into_inner())
+}
+
+/// `z`, `x`, `y` of a `/{z}/{x}/{y}.png` path, if it names a tile that exists
+fn parse_tile(path: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = path.strip_prefix('/')?.strip_suffix(".png")?.split('/');
+    let z: u32 = parts.next()?.parse().ok()?;
+    let x: u32 = parts.next()?.parse().ok()?;
+    let y: u32 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || z > MAX_LEVEL || x as u64 >= 1 << z || y as u64 >= 1 << z {
+        return None;
+    }
+    Some((z, x, y))
+}
+
+fn respond(stream: &mut TcpStream, status: &str, kind: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        kind,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn handle(mut stream: TcpStream, args: &Args, cache: &Cache) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers don't matter, but they have to be read before answering
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+    if path == "/" || path == "/index.html" {
+        return respond(&mut stream, "200 OK", "text/html", VIEWER.as_bytes());
+    }
+    let (z, x, y) = match parse_tile(path) {
+        Some(tile) => tile,
+        None => return respond(&mut stream, "404 Not Found", "text/plain", b"no such tile"),
+    };
+    let cached = cache.lock().unwrap().get(&(z, x, y)).cloned();
+    let png = match cached {
+        Some(png) => png,
+        None => {
+            let png = Arc::new(render_png(args, z, x, y)?);
+            cache.lock().unwrap().put((z, x, y), png.clone());
+            png
+        }
+    };
+    respond(&mut stream, "200 OK", "image/png", &png)
+}
+
+/// Serves the viewer at `/` and tiles of `args`' scene at `/{z}/{x}/{y}.png`,
+/// keeping the last `--cache-tiles` rendered tiles in memory
+pub fn serve(args: &Args, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("serving on http://{}/", listener.local_addr()?);
+    let cache: Arc<Cache> = Arc::new(Mutex::new(LruCache::new(
+        NonZeroUsize::new(args.cache_tiles).unwrap_or(NonZeroUsize::MIN),
+    )));
+    let args = Arc::new(args.clone());
+    for stream in listener.incoming().flatten() {
+        let (args, cache) = (args.clone(), cache.clone());
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &args, &cache) {
+                eprintln!("request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_paths_are_parsed() {
+        assert_eq!(parse_tile("/0/0/0.png"), Some((0, 0, 0)));
+        assert_eq!(parse_tile("/3/7/2.png"), Some((3, 7, 2)));
+        for path in [
+            "/3/8/2.png",
+            "/1/0/0",
+            "/1/0/0/0.png",
+            "/a/0/0.png",
+            "/99/0/0.png",
+        ] {
+            assert_eq!(parse_tile(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn level_0_is_the_scene_view() {
+        let args = Args::new();
+        let tile = tile_args(&args, 0, 0, 0);
+        assert_eq!((tile.origin, tile.zoom), (args.origin, args.zoom));
+        assert_eq!((tile.width, tile.height), (TILE, TILE));
+    }
+
+    #[test]
+    fn children_split_their_parent_in_four() {
+        let args = Args::new();
+        let d = 0.5 / args.zoom;
+        for (x, y, dx, dy) in [(0, 0, -d, -d), (1, 0, d, -d), (0, 1, -d, d), (1, 1, d, d)] {
+            let tile = tile_args(&args, 1, x, y);
+            assert_eq!(tile.zoom, args.zoom * 2.0);
+            assert!((tile.origin - args.origin - Cf64::new(dx, dy)).norm() < 1e-12);
+        }
+    }
+}
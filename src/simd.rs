@@ -0,0 +1,263 @@
+// fracgen
+// Batched SIMD iteration
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// The built-in formulas iterated `LANES` samples at a time, written as plain
+// loops over lane arrays so the compiler turns them into vector instructions.
+// The same code is compiled once for the baseline target and once with AVX2,
+// which one runs is decided at runtime. Every operation is done in the same
+// order as `Renderer::sample`, so both give bit for bit the same iteration
+// counts, orbits, derivatives and periods. Only the smooth count `s` can
+// differ in its last bits, the kernel sums it with its own `exp`.
+
+use num::complex::Complex;
+
+use crate::{formula::Formula, iterdata::Sample};
+
+type Cf64 = Complex<f64>;
+
+/// Samples iterated together, one AVX2 register of f64s
+pub const LANES: usize = 4;
+
+/// How close (relative to |z|) an orbit has to come back to count as a cycle,
+/// a few ulps above what rounding alone can produce
+pub const PERIOD_EPS: f64 = 1024.0 * f64::EPSILON;
+
+/// Which built-in iteration the batched path replaces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kernel {
+    /// only the mandelbrot and the burning ship have a kernel
+    pub formula: Formula,
+    /// `der_bail` instead of `default_bail`
+    pub der_bail: bool,
+    /// adds `--julia` instead of the point itself
+    pub julia: bool,
+}
+
+/// Name of the instruction set the batched path runs with on this CPU
+pub fn isa() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        return "avx2";
+    }
+    "baseline"
+}
+
+/// `e^x` from only arithmetic and bit operations, so a loop of it over lanes
+/// vectorizes. Within an ulp or two of `f64::exp`
+#[inline(always)]
+pub fn exp(x: f64) -> f64 {
+    const LOG2E: f64 = std::f64::consts::LOG2_E;
+    const LN2_HI: f64 = 6.931_471_803_691_238e-1;
+    const LN2_LO: f64 = 1.908_214_929_270_587_7e-10;
+    // adding 1.5 * 2^52 rounds to an integer and leaves it in the low bits
+    const SHIFT: f64 = 6_755_399_441_055_744.0;
+    let small = x < -708.0;
+    let x = x.clamp(-708.0, 709.0);
+    let kf = x * LOG2E + SHIFT;
+    let k = kf - SHIFT;
+    let r = x - k * LN2_HI - k * LN2_LO;
+    // Taylor series, |r| <= ln(2) / 2 so 13 terms are below an ulp
+    let mut p = 1.0 / 6_227_020_800.0;
+    for f in [
+        479_001_600.0,
+        39_916_800.0,
+        3_628_800.0,
+        362_880.0,
+        40_320.0,
+        5_040.0,
+        720.0,
+        120.0,
+        24.0,
+        6.0,
+        2.0,
+        1.0,
+        1.0,
+    ] {
+        p = p * r + 1.0 / f;
+    }
+    let scale = f64::from_bits(kf.to_bits().wrapping_add(1023) << 52);
+    if small {
+        0.0
+    } else {
+        p * scale
+    }
+}
+
+/// Iterates `LANES` orbits starting at `z`, `k` is what each lane adds per
+/// step. Lanes that aren't `live` are left alone and come back unusable
+pub fn iterate(
+    kernel: Kernel,
+    z: [Cf64; LANES],
+    k: [Cf64; LANES],
+    live: [bool; LANES],
+    limit: f64,
+    bail: f64,
+    derbail: f64,
+) -> [Sample; LANES] {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // safe, the CPU was just checked for the features it's compiled with
+        return unsafe { iterate_avx2(kernel, z, k, live, limit, bail, derbail) };
+    }
+    dispatch(kernel, z, k, live, limit, bail, derbail)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn iterate_avx2(
+    kernel: Kernel,
+    z: [Cf64; LANES],
+    k: [Cf64; LANES],
+    live: [bool; LANES],
+    limit: f64,
+    bail: f64,
+    derbail: f64,
+) -> [Sample; LANES] {
+    dispatch(kernel, z, k, live, limit, bail, derbail)
+}
+
+/// Picks the loop compiled for `kernel`, so the lanes don't branch on it
+#[inline(always)]
+fn dispatch(
+    kernel: Kernel,
+    z: [Cf64; LANES],
+    k: [Cf64; LANES],
+    live: [bool; LANES],
+    limit: f64,
+    bail: f64,
+    derbail: f64,
+) -> [Sample; LANES] {
+    // julia sets take the derivative by the start, which `k` doesn't move
+    let dc = if kernel.julia { 0.0 } else { 1.0 };
+    match (kernel.formula, kernel.der_bail) {
+        (Formula::Mandelbrot, false) => {
+            iterate_lanes::<false, false>(z, k, dc, live, limit, bail, derbail)
+        }
+        (Formula::Mandelbrot, true) => {
+            iterate_lanes::<false, true>(z, k, dc, live, limit, bail, derbail)
+        }
+        (Formula::BurningShip, false) => {
+            iterate_lanes::<true, false>(z, k, dc, live, limit, bail, derbail)
+        }
+        (Formula::BurningShip, true) => {
+            iterate_lanes::<true, true>(z, k, dc, live, limit, bail, derbail)
+        }
+        (formula, _) => unreachable!("no kernel for {}", formula),
+    }
+}
+
+/// `SHIP` folds z into the first quadrant before squaring (the burning
+/// ship), `DER_BAIL` bails on the derivative as well as on z
+#[inline(always)]
+fn iterate_lanes<const SHIP: bool, const DER_BAIL: bool>(
+    z: [Cf64; LANES],
+    k: [Cf64; LANES],
+    dc: f64,
+    live: [bool; LANES],
+    limit: f64,
+    bail: f64,
+    derbail: f64,
+) -> [Sample; LANES] {
+    let (kr, ki) = (k.map(|k| k.re), k.map(|k| k.im));
+    let (mut zr, mut zi) = (z.map(|z| z.re), z.map(|z| z.im));
+    let (mut dr, mut di) = ([1.0; LANES], [0.0; LANES]);
+    let (mut tr, mut ti) = ([1.0; LANES], [0.0; LANES]);
+    let (mut i, mut s) = ([0.0; LANES], [0.0; LANES]);
+    let (mut cr, mut ci) = (zr, zi);
+    let (mut power, mut lam, mut period) = ([1u32; LANES], [0u32; LANES], [0u32; LANES]);
+    let mut active = live;
+    let eps = PERIOD_EPS * PERIOD_EPS;
+    loop {
+        let mut any = false;
+        for l in 0..LANES {
+            let inside = if DER_BAIL {
+                let (sr, si) = (tr[l] * tr[l] - ti[l] * ti[l], tr[l] * ti[l] + ti[l] * tr[l]);
+                let n = zr[l] * zr[l] + zi[l] * zi[l];
+                sr * sr + si * si < derbail && n * n < bail
+            } else {
+                zr[l] * zr[l] + zi[l] * zi[l] < bail
+            };
+            active[l] = active[l] && inside && i[l] < limit;
+            any |= active[l];
+        }
+        if !any {
+            break;
+        }
+        for l in 0..LANES {
+            let a = active[l];
+            let ntr = tr[l] + dr[l];
+            let nti = ti[l] + di[l];
+            let (ar, ai) = (dr[l] * 2.0, di[l] * 2.0);
+            let ndr = (ar * zr[l] - ai * zi[l]) + dc;
+            let ndi = (ar * zi[l] + ai * zr[l]) + 0.0;
+            let (wr, wi) = if SHIP {
+                (zr[l].abs(), zi[l].abs())
+            } else {
+                (zr[l], zi[l])
+            };
+            let nzr = (wr * wr - wi * wi) + kr[l];
+            let nzi = (wr * wi + wi * wr) + ki[l];
+            let ni = i[l] + 1.0;
+            let (pr, pi) = (nzr + 1.0, nzi);
+            let ns = s[l] + exp(-(pr * pr + pi * pi));
+            let nlam = lam[l] + 1;
+            let (er, ei) = (nzr - cr[l], nzi - ci[l]);
+            let cycle = er * er + ei * ei < eps * (nzr * nzr + nzi * nzi).max(1.0);
+            let jump = !cycle && nlam == power[l];
+
+            tr[l] = if a { ntr } else { tr[l] };
+            ti[l] = if a { nti } else { ti[l] };
+            dr[l] = if a { ndr } else { dr[l] };
+            di[l] = if a { ndi } else { di[l] };
+            zr[l] = if a { nzr } else { zr[l] };
+            zi[l] = if a { nzi } else { zi[l] };
+            i[l] = if a && cycle {
+                limit
+            } else if a {
+                ni
+            } else {
+                i[l]
+            };
+            s[l] = if a && cycle {
+                limit
+            } else if a {
+                ns
+            } else {
+                s[l]
+            };
+            period[l] = if a && cycle { nlam } else { period[l] };
+            cr[l] = if a && jump { nzr } else { cr[l] };
+            ci[l] = if a && jump { nzi } else { ci[l] };
+            power[l] = if a && jump { power[l] * 2 } else { power[l] };
+            lam[l] = if a && jump {
+                0
+            } else if a {
+                nlam
+            } else {
+                lam[l]
+            };
+            active[l] = a && !cycle;
+        }
+    }
+    std::array::from_fn(|l| Sample {
+        i: i[l],
+        s: s[l],
+        z: Cf64::new(zr[l], zi[l]),
+        der: Cf64::new(dr[l], di[l]),
+        period: period[l],
+    })
+}
@@ -0,0 +1,241 @@
+// fracgen
+// Tiled rendering
+// (C) 2022 by Jacob (ahhhh6980@gmail.com)
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path,
+    time::Instant,
+};
+
+use image::ImageFormat;
+
+use crate::renderer::{Args, Functs, Renderer};
+
+/// Splits a `width`x`height` frame into `tile`x`tile` pieces, the last
+/// row and column are cut short when the frame isn't a multiple of `tile`
+#[derive(Clone, Copy, Debug)]
+pub struct TileGrid {
+    pub width: i32,
+    pub height: i32,
+    pub tile: i32,
+    pub cols: i32,
+    pub rows: i32,
+}
+
+impl TileGrid {
+    pub fn new(width: i32, height: i32, tile: i32) -> TileGrid {
+        TileGrid {
+            width,
+            height,
+            tile,
+            cols: (width + tile - 1) / tile,
+            rows: (height + tile - 1) / tile,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        (self.cols * self.rows) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// (x, y, w, h) of tile (`tx`, `ty`) in frame pixels
+    pub fn rect(&self, tx: i32, ty: i32) -> (i32, i32, i32, i32) {
+        let (x, y) = (tx * self.tile, ty * self.tile);
+        (
+            x,
+            y,
+            self.tile.min(self.width - x),
+            self.tile.min(self.height - y),
+        )
+    }
+
+    pub fn path(&self, dir: &str, tx: i32, ty: i32) -> String {
+        format!("{}{}{}_{}.png", dir, path::MAIN_SEPARATOR, ty, tx)
+    }
+}
+
+/// Renders one tile into its own image, memory use only depends on the tile size
+pub fn render_tile(args: &Args, functs: &Functs, grid: &TileGrid, tx: i32, ty: i32) -> Renderer {
+    let (x, y, w, h) = grid.rect(tx, ty);
+    render_rect(args, functs, x, y, w, h)
+}
+
+/// Renders the `w`x`h` window at (`x`, `y`) of `args`' frame with all its
+/// samples and returns it processed
+pub fn render_rect(args: &Args, functs: &Functs, x: i32, y: i32, w: i32, h: i32) -> Renderer {
+    // finished tile files are the checkpoint in tiled mode
+    let args = Args {
+        checkpoint: None,
+        ..args.clone()
+    };
+    let mut rend = Renderer::tile(args, functs.clone(), x, y, w, h);
+    rend.render_samples(rend.args.samples, false);
+    if rend.args.noise > 0.0 {
+        let (step, noise, max) = (
+            rend.args.adaptive_step,
+            rend.args.noise,
+            rend.args.max_samples,
+        );
+        rend.render_adaptive(step, noise, max);
+    }
+    rend.process_image();
+    rend
+}
+
+/// Saves through a temporary file, so a tile on disk is always complete
+/// and `--resume` can trust any file it finds
+pub fn save_tile(rend: &Renderer, file: &str) -> io::Result<()> {
+    let tmp = format!("{}.tmp", file);
+    rend.image
+        .save_with_format(&tmp, ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    fs::rename(&tmp, file)
+}
+
+pub fn render_tiles(args: &Args, functs: &Functs, grid: &TileGrid, dir: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for ty in 0..grid.rows {
+        for tx in 0..grid.cols {
+            if args.resume && path::Path::new(&grid.path(dir, tx, ty)).exists() {
+                continue;
+            }
+            let now = Instant::now();
+            let rend = render_tile(args, functs, grid, tx, ty);
+            save_tile(&rend, &grid.path(dir, tx, ty))?;
+            println!(
+                "tile {}/{} in {}ms",
+                (ty * grid.cols + tx) + 1,
+                grid.len(),
+                now.elapsed().as_millis()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Stitches the tiles in `dir` into one png, streaming scanlines so only a
+/// row of each open tile is held in memory at a time
+pub fn assemble_png(grid: &TileGrid, dir: &str, out: &str) -> io::Result<()> {
+    let file = BufWriter::new(File::create(out)?);
+    let mut encoder = png::Encoder::new(file, grid.width as u32, grid.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?.into_stream_writer()?;
+    for ty in 0..grid.rows {
+        let mut readers = Vec::with_capacity(grid.cols as usize);
+        for tx in 0..grid.cols {
+            let decoder = png::Decoder::new(BufReader::new(File::open(grid.path(dir, tx, ty))?));
+            readers.push(decoder.read_info()?);
+        }
+        let (_, _, _, h) = grid.rect(0, ty);
+        for _ in 0..h {
+            for reader in readers.iter_mut() {
+                match reader.next_row()? {
+                    Some(row) => writer.write_all(row.data())?,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "tile is shorter than its grid row",
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_covers_the_frame_once() {
+        let grid = TileGrid::new(70, 45, 32);
+        assert_eq!((grid.cols, grid.rows, grid.len()), (3, 2, 6));
+        let mut covered = vec![0; 70 * 45];
+        for ty in 0..grid.rows {
+            for tx in 0..grid.cols {
+                let (x, y, w, h) = grid.rect(tx, ty);
+                for py in y..y + h {
+                    for px in x..x + w {
+                        covered[(py * 70 + px) as usize] += 1;
+                    }
+                }
+            }
+        }
+        assert!(covered.iter().all(|c| *c == 1));
+    }
+
+    #[test]
+    fn tiles_match_the_whole_frame() {
+        let mut args = Args::new();
+        (args.width, args.height, args.samples, args.limit) = (40, 30, 2, 128.0);
+        args.seed = Some(7);
+        let functs = Functs::from_args(&args);
+        let mut whole = Renderer::new(args.clone(), functs.clone());
+        whole.render_samples(args.samples, false);
+        whole.process_image();
+        let grid = TileGrid::new(args.width, args.height, 16);
+        for ty in 0..grid.rows {
+            for tx in 0..grid.cols {
+                let (x, y, w, h) = grid.rect(tx, ty);
+                let tile = render_tile(&args, &functs, &grid, tx, ty);
+                for py in 0..h {
+                    for px in 0..w {
+                        assert_eq!(
+                            tile.image.get_pixel(px as u32, py as u32),
+                            whole.image.get_pixel((x + px) as u32, (y + py) as u32)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resume_renders_over_half_written_tiles() {
+        let dir = std::env::temp_dir().join(format!("fracgen-tiles-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let mut args = Args::new();
+        (args.width, args.height, args.limit) = (40, 30, 64.0);
+        args.resume = true;
+        let grid = TileGrid::new(args.width, args.height, 16);
+        // what a render killed while saving the first tile leaves behind
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{}.tmp", grid.path(dir, 0, 0)), b"\x89PNG").unwrap();
+        render_tiles(&args, &Functs::from_args(&args), &grid, dir).unwrap();
+        for ty in 0..grid.rows {
+            for tx in 0..grid.cols {
+                let (_, _, w, h) = grid.rect(tx, ty);
+                let tile = image::open(grid.path(dir, tx, ty)).unwrap();
+                assert_eq!((tile.width(), tile.height()), (w as u32, h as u32));
+            }
+        }
+        let left = fs::read_dir(dir).unwrap().filter(|e| {
+            let name = e.as_ref().unwrap().file_name();
+            name.to_str().unwrap().ends_with(".tmp")
+        });
+        assert_eq!(left.count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
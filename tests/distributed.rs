@@ -0,0 +1,118 @@
+// A coordinator and two workers on localhost against the same render done
+// in one process
+
+use std::{
+    io::Read,
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use fracgen::{coordinate_on, work, Args, Filter, Functs, Renderer};
+
+fn scene() -> Args {
+    let mut args = Args::new();
+    (args.width, args.height, args.samples, args.limit) = (48, 40, 2, 128.0);
+    args.tile = 16;
+    args.seed = Some(3);
+    args
+}
+
+fn local(args: &Args) -> Renderer {
+    let mut rend = Renderer::new(args.clone(), Functs::from_args(args));
+    rend.render_samples(args.samples, false);
+    if args.noise > 0.0 {
+        rend.render_adaptive(args.adaptive_step, args.noise, args.max_samples);
+    }
+    rend.process_image();
+    rend
+}
+
+/// Renders `args` with two workers, `stall` first connects one that takes a
+/// tile and never answers
+fn distributed(args: &Args, stall: bool) -> Renderer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut threads = Vec::new();
+    if stall {
+        let stalled = TcpStream::connect(addr).unwrap();
+        threads.push(thread::spawn(move || {
+            let mut stalled = stalled;
+            let mut buf = [0u8; 256];
+            // holds on to its tile until the coordinator hangs up
+            while matches!(stalled.read(&mut buf), Ok(n) if n > 0) {}
+        }));
+        // the stalled worker is served first
+        thread::sleep(Duration::from_millis(200));
+    }
+    for _ in 0..2 {
+        threads.push(thread::spawn(move || work(addr).unwrap()));
+    }
+    let mut rend = coordinate_on(args, &Functs::from_args(args), listener).unwrap();
+    for t in threads {
+        t.join().unwrap();
+    }
+    rend.process_image();
+    rend
+}
+
+/// Largest difference of any channel of any pixel
+fn max_diff(a: &Renderer, b: &Renderer) -> u8 {
+    a.image
+        .pixels()
+        .zip(b.image.pixels())
+        .flat_map(|(p, q)| (0..4).map(move |k| p.0[k].abs_diff(q.0[k])))
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn accumulators_match_a_local_render() {
+    let args = scene();
+    assert_eq!(distributed(&args, false).image, local(&args).image);
+}
+
+#[test]
+fn iteration_data_matches_a_local_render() {
+    let args = Args {
+        send_iters: true,
+        ..scene()
+    };
+    assert_eq!(distributed(&args, false).image, local(&args).image);
+}
+
+#[test]
+fn filtered_renders_match_a_local_render() {
+    for send_iters in [false, true] {
+        let args = Args {
+            send_iters,
+            filter: Filter::Gaussian,
+            filter_radius: 1.5,
+            ..scene()
+        };
+        // samples are added up in another order, only rounding differs
+        assert!(max_diff(&distributed(&args, false), &local(&args)) <= 1);
+    }
+}
+
+#[test]
+fn workers_take_adaptive_samples() {
+    let args = Args {
+        noise: 0.02,
+        max_samples: 8,
+        ..scene()
+    };
+    let (dist, local) = (distributed(&args, false), local(&args));
+    assert_eq!(dist.image, local.image);
+    assert_eq!(dist.extra_samples, local.extra_samples);
+    assert!(dist.extra_samples.iter().flatten().any(|n| *n > 0));
+}
+
+#[test]
+fn tiles_of_stalled_workers_are_handed_out_again() {
+    let args = Args {
+        worker_timeout: 1,
+        ..scene()
+    };
+    assert_eq!(distributed(&args, true).image, local(&args).image);
+}